@@ -1,10 +1,14 @@
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
 
-use env_logger;
-
 use super::compress;
+use super::config;
+use super::cue;
+use super::error::Error;
+use super::init;
 use super::link;
+use super::merge_bin;
+use super::name;
 use super::playlist;
 use super::rename;
 
@@ -48,7 +52,7 @@ macro_rules! define_commands {
 
         // Automatically generate the dispatch implementation
         impl $enum_name {
-            fn dispatch(self) -> Result<(), String> {
+            fn dispatch(self) -> Result<(), Error> {
                 match self {
                     // Generate a match arm for each variant
                     // Each arm extracts the args and calls its dispatch method
@@ -66,14 +70,19 @@ define_commands! {
     enum Commands {
         #[clap(visible_alias = "chd")]
         Compress(compress::Args),
+        Config(config::Args),
+        Cue(cue::Args),
+        Init(init::Args),
         Link(link::Args),
+        MergeBin(merge_bin::Args),
+        Name(name::Args),
         #[clap(visible_alias = "m3u")]
         Playlist(playlist::Args),
         Rename(rename::Args),
     }
 }
 
-pub fn dispatch() -> Result<(), String> {
+pub fn dispatch() -> Result<(), Error> {
     let args = Cli::parse();
 
     env_logger::Builder::new()
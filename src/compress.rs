@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
-use log::{debug, log_enabled, warn, Level};
+use log::{debug, error, log_enabled, warn, Level};
 
 use super::config::load_config_recursively;
-use super::utils::{capture_output, find_files_with_extension, require_command, stream_output};
+use super::disc::find_discs;
+use super::error::Error;
+use super::utils::{capture_output, require_command, stream_output};
 
 #[derive(Debug, clap::Args)]
 #[command(about = "Compress games")]
@@ -12,78 +15,310 @@ pub struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    // The arguments for CHD are split up so that clap can tell when they've been provided without a
+    // subcommand.
     #[command(flatten)]
-    chd: ChdArgs,
+    compress: Option<CompressArgs>,
+
+    #[arg(long, help = "Create a compressed DVD image")]
+    dvd: bool,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Commands {
     #[command(about = "Convert files to CHD")]
     Chd(ChdArgs),
+
+    #[command(about = "Convert files to CSO")]
+    Cso(CompressArgs),
+
+    #[command(about = "Convert files to RVZ")]
+    Rvz(CompressArgs),
+
+    #[command(about = "Convert files to ZSO")]
+    Zso(CompressArgs),
 }
 
 #[derive(Debug, clap::Args)]
 struct ChdArgs {
+    #[command(flatten)]
+    compress: CompressArgs,
+
+    #[arg(long, help = "Create a compressed DVD image")]
+    dvd: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct CompressArgs {
     #[arg(help = "The file to compress")]
     source: PathBuf,
 
     #[arg(help = "Where to place the compressed file, defaults to the current directory")]
     dest: Option<PathBuf>,
 
-    #[arg(long, help = "Create a compressed DVD image")]
-    dvd: bool,
-
-    #[arg(short, long, help = "Force overwriting existing CHD files")]
+    #[arg(short, long, help = "Force overwriting existing compressed files")]
     force: bool,
+
+    #[arg(short, long, help = "The compression profile to use, e.g., psp")]
+    profile: Option<String>,
+
+    #[arg(long, help = "Don't compress the files")]
+    dry_run: bool,
 }
 
 impl Args {
-    pub fn dispatch(self) -> Result<(), String> {
-        let cmd = self.command.unwrap_or(Commands::Chd(self.chd));
+    pub fn dispatch(self) -> Result<(), Error> {
+        let cmd = self
+            .command
+            .or(self.compress.map(|compress| {
+                Commands::Chd(ChdArgs {
+                    compress,
+                    dvd: self.dvd,
+                })
+            }))
+            .ok_or_else(|| Error::from("No command provided"))?;
         match cmd {
-            Commands::Chd(args) => {
-                compress_to_chd(args.source, args.dest.clone(), args.dvd, args.force)
-            }
+            Commands::Chd(args) => compress(&Chd { as_dvd: args.dvd }, args.compress),
+            Commands::Cso(args) => compress(&Maxcso { format: "cso" }, args),
+            Commands::Rvz(args) => compress(&DolphinTool { format: "rvz" }, args),
+            Commands::Zso(args) => compress(&Maxcso { format: "zso" }, args),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct Config {
+    #[serde(default)]
     compress: CompressConfig,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            compress: CompressConfig::default(),
-        }
-    }
-}
-
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct CompressConfig {
     pub extensions: Vec<String>,
     pub format: String,
+    #[serde(default)]
+    pub profiles: HashMap<String, CompressProfile>,
 }
 
 impl Default for CompressConfig {
     fn default() -> Self {
         Self {
-            extensions: vec!["cue".to_string(), "iso".to_string()],
+            extensions: vec![
+                "cue".to_string(),
+                "gdi".to_string(),
+                "toc".to_string(),
+                "iso".to_string(),
+            ],
             format: "cd".to_string(),
+            profiles: HashMap::new(),
         }
     }
 }
 
-fn compress_to_chd(
-    source: PathBuf,
-    dest: Option<PathBuf>,
+impl CompressConfig {
+    pub fn get_profile(&self, name: &str) -> Result<&CompressProfile, Error> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| Error::Config(format!("Compression profile '{}' not found", name)))
+    }
+}
+
+// Profiles are keyed by system (e.g., `[compress.profiles.psp]`) so that each system can use the
+// settings that work best for it. Any setting that isn't specified falls back to the compressor's
+// default, and settings that a compressor doesn't support are ignored.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct CompressProfile {
+    pub codecs: Option<Vec<String>>,
+    pub format: Option<String>,
+    pub hunk_size: Option<u32>,
+    pub processors: Option<u32>,
+}
+
+// A compressor wraps an external command. File discovery, skipping existing files, and logging are
+// handled the same way for every compressor, so each one only needs to describe how it's invoked.
+trait Compressor {
+    // The name of the command to run.
+    fn command(&self) -> &str;
+
+    // The extension used for compressed files.
+    fn extension(&self) -> &str;
+
+    // The extensions of files that can be compressed.
+    fn input_extensions(&self, config: &CompressConfig) -> Vec<String>;
+
+    fn args(
+        &self,
+        config: &CompressConfig,
+        profile: &CompressProfile,
+        input: &str,
+        output: &str,
+        force: bool,
+    ) -> Vec<String>;
+}
+
+struct Chd {
     as_dvd: bool,
-    force: bool,
-) -> Result<(), String> {
-    let output_path = dest.unwrap_or(PathBuf::new());
-    debug!("Compressing from {source:?} to {output_path:?}");
+}
+
+impl Compressor for Chd {
+    fn command(&self) -> &str {
+        "chdman"
+    }
+
+    fn extension(&self) -> &str {
+        "chd"
+    }
+
+    fn input_extensions(&self, config: &CompressConfig) -> Vec<String> {
+        config.extensions.clone()
+    }
+
+    fn args(
+        &self,
+        config: &CompressConfig,
+        profile: &CompressProfile,
+        input: &str,
+        output: &str,
+        force: bool,
+    ) -> Vec<String> {
+        let format = if self.as_dvd {
+            "dvd"
+        } else {
+            profile.format.as_ref().unwrap_or(&config.format)
+        };
+
+        let mut args = vec![
+            format!("create{}", format),
+            "-i".to_string(),
+            input.to_string(),
+            "-o".to_string(),
+            output.to_string(),
+        ];
+        if let Some(ref codecs) = profile.codecs {
+            args.extend(["--compression".to_string(), codecs.join(",")]);
+        }
+        if let Some(hunk_size) = profile.hunk_size {
+            args.extend(["--hunksize".to_string(), hunk_size.to_string()]);
+        }
+        if let Some(processors) = profile.processors {
+            args.extend(["--numprocessors".to_string(), processors.to_string()]);
+        }
+        if force {
+            args.push("--force".to_string());
+        }
+        args
+    }
+}
+
+// dolphin-tool handles GameCube and Wii images. It always overwrites its output.
+struct DolphinTool {
+    format: &'static str,
+}
+
+impl Compressor for DolphinTool {
+    fn command(&self) -> &str {
+        "dolphin-tool"
+    }
+
+    fn extension(&self) -> &str {
+        self.format
+    }
+
+    fn input_extensions(&self, _config: &CompressConfig) -> Vec<String> {
+        vec!["gcm".to_string(), "iso".to_string(), "wbfs".to_string()]
+    }
+
+    fn args(
+        &self,
+        _config: &CompressConfig,
+        profile: &CompressProfile,
+        input: &str,
+        output: &str,
+        _force: bool,
+    ) -> Vec<String> {
+        let codec = profile
+            .codecs
+            .as_ref()
+            .and_then(|codecs| codecs.first())
+            .map_or("zstd", |codec| codec.as_str());
+        let block_size = profile.hunk_size.unwrap_or(131072);
+
+        vec![
+            "convert".to_string(),
+            "-i".to_string(),
+            input.to_string(),
+            "-o".to_string(),
+            output.to_string(),
+            "-f".to_string(),
+            self.format.to_string(),
+            "-c".to_string(),
+            codec.to_string(),
+            "-l".to_string(),
+            "5".to_string(),
+            "-b".to_string(),
+            block_size.to_string(),
+        ]
+    }
+}
+
+// maxcso handles PSP (and PS2) images. It always overwrites its output.
+struct Maxcso {
+    format: &'static str,
+}
+
+impl Compressor for Maxcso {
+    fn command(&self) -> &str {
+        "maxcso"
+    }
+
+    fn extension(&self) -> &str {
+        self.format
+    }
+
+    fn input_extensions(&self, _config: &CompressConfig) -> Vec<String> {
+        vec!["iso".to_string()]
+    }
+
+    fn args(
+        &self,
+        _config: &CompressConfig,
+        profile: &CompressProfile,
+        input: &str,
+        output: &str,
+        _force: bool,
+    ) -> Vec<String> {
+        let format = if self.format == "cso" {
+            "cso1"
+        } else {
+            self.format
+        };
+
+        let mut args = vec![format!("--format={}", format)];
+        if let Some(hunk_size) = profile.hunk_size {
+            args.push(format!("--block={}", hunk_size));
+        }
+        if let Some(processors) = profile.processors {
+            args.push(format!("--threads={}", processors));
+        }
+        args.extend([input.to_string(), "-o".to_string(), output.to_string()]);
+        args
+    }
+}
+
+fn compress(compressor: &dyn Compressor, args: CompressArgs) -> Result<(), Error> {
+    let CompressArgs {
+        source,
+        dest,
+        force,
+        profile,
+        dry_run,
+    } = args;
+
+    let output_path = dest.unwrap_or_default();
+    debug!(
+        "Compressing from {source:?} to {output_path:?} with {}",
+        compressor.command()
+    );
 
     let config: CompressConfig = load_config_recursively(&source)
         .unwrap_or_else(|_| {
@@ -92,20 +327,36 @@ fn compress_to_chd(
         })
         .compress;
 
-    let extensions: Vec<&str> = config.extensions.iter().map(|s| s.as_str()).collect();
-    let files_to_compress = find_files_with_extension(&source, &extensions)?;
+    let default_profile = CompressProfile::default();
+    let profile = match profile {
+        Some(ref name) => {
+            debug!("Using compression profile {name}");
+            config.get_profile(name)?
+        }
+        None => &default_profile,
+    };
 
-    let mut image_format: &str = &format!("create{}", config.format);
-    if as_dvd {
-        image_format = "createdvd";
-    }
+    let input_extensions = compressor.input_extensions(&config);
+    let extensions: Vec<&str> = input_extensions.iter().map(|s| s.as_str()).collect();
+    let discs_to_compress = find_discs(&source, &extensions)?;
 
-    for file in files_to_compress {
+    for disc in discs_to_compress {
+        let missing_tracks = disc.missing_tracks();
+        if !missing_tracks.is_empty() {
+            error!(
+                "{} is missing {:?}. Skipping.",
+                disc.path.display(),
+                missing_tracks
+            );
+            continue;
+        }
+
+        let file = disc.path;
         let file_name = file
             .file_name()
             .ok_or_else(|| format!("Failed to get filename for {}", file.display()))?;
         let mut output_file = output_path.join(file_name);
-        output_file.set_extension("chd");
+        output_file.set_extension(compressor.extension());
         if !force && output_file.exists() {
             warn!("{} exists. Skipping.", output_file.display());
             continue;
@@ -121,20 +372,187 @@ fn compress_to_chd(
             )
         })?;
 
-        let mut command = require_command("chdman")?;
-        command.args(&[image_format, "-i", file_str, "-o", output_str]);
-        if force {
-            command.arg("--force");
+        let compressor_args = compressor.args(&config, profile, file_str, output_str, force);
+
+        if dry_run {
+            error!(
+                "Would compress {} with {} {}",
+                file.display(),
+                compressor.command(),
+                compressor_args.join(" ")
+            );
+            continue;
         }
+
+        let mut command = require_command(compressor.command())?;
+        command.args(&compressor_args);
         let error_message = format!("Failed to compress {}", file.display());
 
         if log_enabled!(Level::Warn) {
             stream_output(&mut command, &error_message)?;
         } else {
             let _ = capture_output(&mut command, &error_message)?;
-            warn!("{} created with {image_format}", output_file.display());
+            warn!(
+                "{} created with {}",
+                output_file.display(),
+                compressor.command()
+            );
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_config_get_profile_finds_profile() {
+        let config = CompressConfig {
+            profiles: HashMap::from([(
+                "psp".to_string(),
+                CompressProfile {
+                    format: Some("dvd".to_string()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let profile = config.get_profile("psp").unwrap();
+        assert_eq!(profile.format, Some("dvd".to_string()));
+    }
+
+    #[test]
+    fn compress_config_get_profile_without_profile() {
+        let config = CompressConfig::default();
+        assert!(config.get_profile("psp").is_err());
+    }
+
+    #[test]
+    fn chd_args_with_all_settings() {
+        let profile = CompressProfile {
+            codecs: Some(vec!["lzma".to_string(), "zlib".to_string()]),
+            format: Some("dvd".to_string()),
+            hunk_size: Some(2048),
+            processors: Some(4),
+        };
+        let chd = Chd { as_dvd: false };
+        assert_eq!(
+            chd.args(&CompressConfig::default(), &profile, "a.iso", "a.chd", true),
+            &[
+                "createdvd",
+                "-i",
+                "a.iso",
+                "-o",
+                "a.chd",
+                "--compression",
+                "lzma,zlib",
+                "--hunksize",
+                "2048",
+                "--numprocessors",
+                "4",
+                "--force"
+            ]
+        );
+    }
+
+    #[test]
+    fn chd_args_without_settings() {
+        let chd = Chd { as_dvd: false };
+        assert_eq!(
+            chd.args(
+                &CompressConfig::default(),
+                &CompressProfile::default(),
+                "a.cue",
+                "a.chd",
+                false
+            ),
+            &["createcd", "-i", "a.cue", "-o", "a.chd"]
+        );
+    }
+
+    #[test]
+    fn chd_args_as_dvd_overrides_profile() {
+        let profile = CompressProfile {
+            format: Some("cd".to_string()),
+            ..Default::default()
+        };
+        let chd = Chd { as_dvd: true };
+        assert_eq!(
+            chd.args(
+                &CompressConfig::default(),
+                &profile,
+                "a.iso",
+                "a.chd",
+                false
+            )[0],
+            "createdvd"
+        );
+    }
+
+    #[test]
+    fn dolphin_tool_args_with_settings() {
+        let profile = CompressProfile {
+            codecs: Some(vec!["lzma2".to_string()]),
+            hunk_size: Some(2097152),
+            ..Default::default()
+        };
+        let dolphin_tool = DolphinTool { format: "rvz" };
+        assert_eq!(
+            dolphin_tool.args(
+                &CompressConfig::default(),
+                &profile,
+                "a.iso",
+                "a.rvz",
+                false
+            ),
+            &[
+                "convert", "-i", "a.iso", "-o", "a.rvz", "-f", "rvz", "-c", "lzma2", "-l", "5",
+                "-b", "2097152"
+            ]
+        );
+    }
+
+    #[test]
+    fn maxcso_args_with_settings() {
+        let profile = CompressProfile {
+            hunk_size: Some(16384),
+            processors: Some(2),
+            ..Default::default()
+        };
+        let maxcso = Maxcso { format: "cso" };
+        assert_eq!(
+            maxcso.args(
+                &CompressConfig::default(),
+                &profile,
+                "a.iso",
+                "a.cso",
+                false
+            ),
+            &[
+                "--format=cso1",
+                "--block=16384",
+                "--threads=2",
+                "a.iso",
+                "-o",
+                "a.cso"
+            ]
+        );
+    }
+
+    #[test]
+    fn maxcso_args_for_zso() {
+        let maxcso = Maxcso { format: "zso" };
+        assert_eq!(
+            maxcso.args(
+                &CompressConfig::default(),
+                &CompressProfile::default(),
+                "a.iso",
+                "a.zso",
+                false
+            ),
+            &["--format=zso", "a.iso", "-o", "a.zso"]
+        );
+    }
+}
@@ -1,106 +1,313 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
 use std::path::{Path, PathBuf};
 
-use super::utils::{find_file_recursively, get_from_env, get_from_env_or_exit};
+use log::{error, info};
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-pub struct Config {
-    pub link: LinkConfig,
+use super::compress;
+use super::error::Error;
+use super::frontend::Preset;
+use super::name;
+use super::playlist;
+use super::systems::find_system;
+use super::utils::{expand_variables, find_file_recursively, get_from_env};
+
+#[derive(Debug, clap::Args)]
+#[command(about = "Manage configuration")]
+pub struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Commands {
+    #[command(about = "Check every config file and show the effective config")]
+    Check,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            link: LinkConfig::default(),
+impl Args {
+    pub fn dispatch(self) -> Result<(), Error> {
+        match self.command {
+            Commands::Check => check(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+// Systems in the global config are a catalog of defaults for each system. A destination's
+// retro.toml only needs to list its systems and override what's different about them.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    pub link: LinkConfig,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub systems: HashMap<String, System>,
+}
+
+// Games are linked from one or more sources. `source` is a single unnamed source, and `sources`
+// has named ones, e.g., one for each drive the backups are spread over. Likewise, `destinations`
+// are unnamed and `profiles` are named destinations with their own settings.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct LinkConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub source: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, SourceConfig>,
+    #[serde(default)]
     pub destinations: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>,
 }
 
-impl Default for LinkConfig {
-    fn default() -> Self {
-        Self {
-            source: "".to_string(),
-            destinations: vec![],
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SourceConfig {
+    pub path: String,
+    #[serde(default)]
+    pub priority: i64,
+}
+
+// A named destination, e.g., a handheld's SD card. When `systems` is set, only those systems are
+// linked to it, and they don't need to be in its retro.toml. The preset is used when its retro.toml
+// doesn't set one.
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ProfileConfig {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub systems: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<Preset>,
+}
+
+// A destination with its path expanded. Destinations in the `destinations` list are named by their
+// path as it's written in the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub name: String,
+    pub path: PathBuf,
+    pub systems: Vec<String>,
+    pub preset: Option<Preset>,
+}
+
+impl Destination {
+    // Apply the destination's profile to its retro.toml.
+    pub fn apply(&self, config: &mut LinkDestinationConfig) {
+        if config.preset.is_none() {
+            config.preset = self.preset;
+        }
+        if self.systems.is_empty() {
+            return;
+        }
+        config.systems.retain(|name, _| self.systems.contains(name));
+        for name in &self.systems {
+            config.systems.entry(name.clone()).or_default();
         }
     }
 }
 
+// A source with its path expanded. Sources with a higher priority come first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub path: PathBuf,
+    pub priority: i64,
+}
+
+// Expand environment variables and `~` in paths. Every variable that isn't set is reported at once
+// so that they can all be fixed together.
+fn expand_paths<'a>(values: impl IntoIterator<Item = &'a String>) -> Result<Vec<PathBuf>, Error> {
+    let mut unresolved = Vec::new();
+    let paths = values
+        .into_iter()
+        .map(|value| expand_variables(value, &mut unresolved).map(PathBuf::from))
+        .collect::<Result<_, _>>()?;
+
+    if !unresolved.is_empty() {
+        unresolved.sort();
+        unresolved.dedup();
+        return Err(Error::Config(format!(
+            "Environment variable(s) not set: {}",
+            unresolved.join(", ")
+        )));
+    }
+    Ok(paths)
+}
+
 impl LinkConfig {
-    pub fn expand_destinations(&self) -> Vec<PathBuf> {
-        self.destinations
+    // Get the unnamed destinations in order, followed by the profiles sorted by name.
+    pub fn expand_destinations(&self) -> Result<Vec<Destination>, Error> {
+        let profiles: Vec<(String, ProfileConfig)> = self
+            .destinations
             .iter()
-            .map(|destination| {
-                if destination.starts_with('$') {
-                    PathBuf::from(get_from_env_or_exit(&destination[1..]))
-                } else {
-                    PathBuf::from(destination.as_str())
-                }
+            .map(|path| {
+                let profile = ProfileConfig {
+                    path: path.clone(),
+                    ..Default::default()
+                };
+                (path.clone(), profile)
             })
-            .collect()
+            .chain(self.profiles.clone())
+            .collect();
+
+        let paths = expand_paths(profiles.iter().map(|(_, profile)| &profile.path))?;
+        Ok(profiles
+            .into_iter()
+            .zip(paths)
+            .map(|((name, profile), path)| Destination {
+                name,
+                path,
+                systems: profile.systems,
+                preset: profile.preset,
+            })
+            .collect())
     }
 
-    pub fn expand_source(&self) -> PathBuf {
-        if self.source.starts_with('$') {
-            PathBuf::from(get_from_env_or_exit(&self.source[1..]))
-        } else {
-            PathBuf::from(self.source.as_str())
+    // Get the destinations with the given names, or every destination when no names are given.
+    pub fn select_destinations(&self, names: &[String]) -> Result<Vec<Destination>, Error> {
+        let destinations = self.expand_destinations()?;
+        if names.is_empty() {
+            return Ok(destinations);
         }
+        let unknown: Vec<&str> = names
+            .iter()
+            .filter(|name| !destinations.iter().any(|d| d.name == **name))
+            .map(|name| name.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            return Err(Error::Config(format!(
+                "Destination(s) not found in the config: {}",
+                unknown.join(", ")
+            )));
+        }
+        Ok(destinations
+            .into_iter()
+            .filter(|destination| names.contains(&destination.name))
+            .collect())
+    }
+
+    // Get every source, highest priority first. Sources with the same priority are sorted by name so
+    // that the order never depends on the config's layout. The unnamed `source` is called `default`.
+    pub fn expand_sources(&self) -> Result<Vec<Source>, Error> {
+        let mut sources: Vec<(&String, &SourceConfig)> = self.sources.iter().collect();
+        let default = SourceConfig {
+            path: self.source.clone(),
+            priority: 0,
+        };
+        let default_name = "default".to_string();
+        if !self.source.is_empty() {
+            if self.sources.contains_key(&default_name) {
+                return Err(Error::Config(
+                    "source and sources.default can't both be set".to_string(),
+                ));
+            }
+            sources.push((&default_name, &default));
+        }
+        if sources.is_empty() {
+            return Err(Error::Config("No source set in the config".to_string()));
+        }
+
+        let paths = expand_paths(sources.iter().map(|(_, source)| &source.path))?;
+        let mut sources: Vec<Source> = sources
+            .into_iter()
+            .zip(paths)
+            .map(|((name, source), path)| Source {
+                name: name.clone(),
+                path,
+                priority: source.priority,
+            })
+            .collect();
+        sources.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Ok(sources)
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct LinkDestinationConfig {
+    #[serde(default)]
+    pub preset: Option<Preset>,
     pub systems: HashMap<String, System>,
 }
 
-impl Default for LinkDestinationConfig {
-    fn default() -> Self {
-        Self {
-            systems: HashMap::new(),
-        }
-    }
-}
-
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct System {
     pub destination: Option<String>,
     pub destinations: Option<Vec<String>>,
-    pub dumper: String,
+    #[serde(
+        deserialize_with = "deserialize_one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub dumper: Vec<String>,
     pub extension: Option<String>,
     pub extensions: Option<Vec<String>>,
     pub extra_path: Option<String>,
 }
 
-impl Default for System {
-    fn default() -> Self {
-        Self {
-            destination: None,
-            destinations: None,
-            dumper: "".to_string(),
-            extension: None,
-            extensions: None,
-            extra_path: None,
-        }
+// A system can come from several dumpers, but most only have one, so a single dumper doesn't need
+// to be in a list.
+fn deserialize_one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
     }
 }
 
 impl LinkDestinationConfig {
     pub fn get_system_names(&self) -> Vec<String> {
-        self.systems.keys().map(|k| k.clone()).collect()
+        self.systems.keys().cloned().collect()
+    }
+
+    // Fill in each system from the destination's preset first, since it's specific to the
+    // destination, then from the catalog, and finally from the built-in systems.
+    pub fn inherit(&mut self, catalog: &HashMap<String, System>) {
+        for (name, system) in self.systems.iter_mut() {
+            let known = find_system(name);
+            if let (Some(preset), Some(known)) = (self.preset, known) {
+                system.inherit(&preset.to_system(known));
+            }
+            if let Some(base) = catalog.get(name) {
+                system.inherit(base);
+            }
+            if let Some(known) = known {
+                system.inherit(&known.to_system());
+            }
+        }
     }
 }
 
-// TODO: Before this logic was moved here, the code had access to both `system` and the config,
-// making the `unwrap_or` part possible. I want to figure out a better way to handle populating
-// instances of the struct so that 1) `system` doesn't need to be passed in as an argument and 2) I
-// don't have to resort to requiring `destination` and `extension` in each config entry.
+// Anything a system doesn't set comes from the built-in system with the same name (or alias), and
+// the name itself is the last resort for systems retro doesn't know about.
 impl System {
+    // Fill in anything that isn't set from the catalog's system. `destination` and `destinations`
+    // are inherited together (as are `extension` and `extensions`) so that setting either one
+    // replaces both.
+    pub fn inherit(&mut self, base: &System) {
+        if self.dumper.is_empty() {
+            self.dumper = base.dumper.clone();
+        }
+        if self.destination.is_none() && self.destinations.is_none() {
+            self.destination = base.destination.clone();
+            self.destinations = base.destinations.clone();
+        }
+        if self.extension.is_none() && self.extensions.is_none() {
+            self.extension = base.extension.clone();
+            self.extensions = base.extensions.clone();
+        }
+        if self.extra_path.is_none() {
+            self.extra_path = base.extra_path.clone();
+        }
+    }
+
     pub fn get_destinations(&self, system: &str) -> Vec<String> {
         if let Some(ref destinations) = self.destinations {
             destinations.clone()
@@ -111,57 +318,481 @@ impl System {
         }
     }
 
+    // Where the system's games can be, one directory for each source and dumper. Sources come first
+    // and dumpers second, both in order of preference.
+    pub fn get_sources(&self, sources: &[PathBuf], system: &str) -> Vec<PathBuf> {
+        sources
+            .iter()
+            .flat_map(|source| {
+                self.dumper
+                    .iter()
+                    .map(move |dumper| source.join(dumper).join(system))
+            })
+            .collect()
+    }
+
     pub fn get_extensions(&self, system: &str) -> Vec<String> {
         if let Some(ref extensions) = self.extensions {
             extensions.clone()
         } else if let Some(ref extension) = self.extension {
             vec![extension.clone()]
+        } else if let Some(known) = find_system(system) {
+            known.get_extensions()
         } else {
             vec![system.to_string()]
         }
     }
 }
 
+// Merge one table into another. Tables are merged key by key, and everything else (including
+// arrays) is replaced.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge_tables(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn get_includes(path: &Path, table: &mut toml::Table) -> Result<Vec<PathBuf>, Error> {
+    let includes = match table.remove("include") {
+        None => vec![],
+        Some(toml::Value::String(include)) => vec![include],
+        Some(toml::Value::Array(includes)) => includes
+            .into_iter()
+            .map(|include| match include {
+                toml::Value::String(include) => Ok(include),
+                _ => Err(Error::Config(format!(
+                    "{}: include must be a list of paths",
+                    path.display()
+                ))),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(Error::Config(format!(
+                "{}: include must be a path or a list of paths",
+                path.display()
+            )))
+        }
+    };
+
+    // Included paths are relative to the file that includes them.
+    let directory = path.parent().unwrap_or(Path::new(""));
+    Ok(expand_paths(&includes)?
+        .into_iter()
+        .map(|include| directory.join(include))
+        .collect())
+}
+
+fn read_config_table_with_includes(
+    path: &Path,
+    including: &mut Vec<PathBuf>,
+) -> Result<toml::Table, Error> {
+    // The same file can be reached through different paths, e.g., `../x/a.toml` from `x/a.toml`.
+    let canonical =
+        fs::canonicalize(path).map_err(|e| Error::io("Failed to read config", path, e))?;
+    if including.contains(&canonical) {
+        return Err(Error::Config(format!(
+            "{} is included by itself",
+            path.display()
+        )));
+    }
+    let contents =
+        fs::read_to_string(path).map_err(|e| Error::io("Failed to read config", path, e))?;
+    let mut table: toml::Table = contents
+        .parse()
+        .map_err(|e| Error::Config(format!("Failed to parse config {}: {}", path.display(), e)))?;
+
+    including.push(canonical);
+    let mut merged = toml::Table::new();
+    for include in get_includes(path, &mut table)? {
+        merge_tables(
+            &mut merged,
+            read_config_table_with_includes(&include, including)?,
+        );
+    }
+    including.pop();
+
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+// Read a config file along with any files listed in its `include`. Included files are merged in
+// order, and anything in the including file overrides them.
+pub fn read_config_table(path: &Path) -> Result<toml::Table, Error> {
+    read_config_table_with_includes(path, &mut Vec::new())
+}
+
+// Load a config file, falling back to the defaults when it doesn't exist.
+pub fn load_config_file<T: serde::de::DeserializeOwned + Default>(path: &Path) -> Result<T, Error> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    read_config_table(path)?.try_into().map_err(|e| {
+        Error::Config(format!(
+            "Failed to load config from {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
 pub fn load_config_recursively<T: serde::Serialize + serde::de::DeserializeOwned + Default>(
     root: &Path,
-) -> Result<T, String> {
+) -> Result<T, Error> {
     let path = find_file_recursively(root, "retro.toml")?
-        .ok_or_else(|| "Failed to find retro.toml file".to_string())?;
-    let path_display = path.display();
-    confy::load_path(&path)
-        .map_err(|e| format!("Failed to load config from {}: {}", path_display, e))
+        .ok_or_else(|| Error::Config("Failed to find retro.toml file".to_string()))?;
+    load_config_file(&path)
 }
 
-pub fn load_global_config() -> Result<Config, String> {
-    let config: Config = if let Ok(path) = get_from_env("RETRO_CONFIG") {
-        let path_display = path.clone();
-        confy::load_path(PathBuf::from(path))
-            .map_err(|e| format!("Failed to load config from {}: {}", path_display, e))?
-    } else {
-        confy::load("retro", "retro").map_err(|e| format!("Failed to load global config: {}", e))?
-    };
+// Like `load_config_recursively`, but fall back to the defaults when there's no retro.toml file.
+pub fn load_optional_config_recursively<
+    T: serde::Serialize + serde::de::DeserializeOwned + Default,
+>(
+    root: &Path,
+) -> Result<T, Error> {
+    match find_file_recursively(root, "retro.toml")? {
+        Some(path) => load_config_file(&path),
+        None => Ok(T::default()),
+    }
+}
 
-    Ok(config)
+pub fn get_global_config_path() -> Result<PathBuf, Error> {
+    if let Ok(path) = get_from_env("RETRO_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    confy::get_configuration_file_path("retro", "retro")
+        .map_err(|e| Error::Config(format!("Failed to find global config: {}", e)))
+}
+
+pub fn load_global_config() -> Result<Config, Error> {
+    load_config_file(&get_global_config_path()?)
 }
 
+// Load a destination's retro.toml, applying its profile and filling in its systems from the
+// catalog.
 pub fn load_link_destination_config(
-    config_file: Option<PathBuf>,
-) -> Result<LinkDestinationConfig, String> {
-    let config_path = config_file.unwrap_or(PathBuf::from("retro.toml"));
-    let config: LinkDestinationConfig = confy::load_path(&config_path).map_err(|e| {
-        format!(
-            "Failed to load config from {}: {}",
-            config_path.display(),
-            e
-        )
-    })?;
+    destination: &Destination,
+    catalog: &HashMap<String, System>,
+) -> Result<LinkDestinationConfig, Error> {
+    let mut config: LinkDestinationConfig = load_config_file(&destination.path.join("retro.toml"))?;
+    destination.apply(&mut config);
+    config.inherit(catalog);
 
     Ok(config)
 }
 
+// What the config amounts to once every file is loaded and every default is filled in.
+#[derive(Debug, Default, serde::Serialize)]
+struct EffectiveConfig {
+    sources: Vec<EffectiveSource>,
+    destinations: BTreeMap<PathBuf, EffectiveDestination>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct EffectiveSource {
+    name: String,
+    path: PathBuf,
+    priority: i64,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct EffectiveDestination {
+    name: String,
+    systems: BTreeMap<String, EffectiveSystem>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct EffectiveSystem {
+    sources: Vec<PathBuf>,
+    destinations: Vec<PathBuf>,
+    extensions: Vec<String>,
+}
+
+// Parse a config file, keeping track of the keys that the config doesn't know about.
+fn parse_config<T: serde::de::DeserializeOwned>(
+    table: &toml::Table,
+) -> Result<(T, Vec<String>), String> {
+    let mut ignored = Vec::new();
+    let config = serde_ignored::deserialize(table.clone(), |path| ignored.push(path.to_string()))
+        .map_err(|e| e.to_string())?;
+    Ok((config, ignored))
+}
+
+// A retro.toml file can be read by several commands, each of which only knows about its own keys.
+// A key is unknown when every one of them ignores it (or one of its parents).
+fn find_unknown_keys(ignored: &[Vec<String>]) -> Vec<String> {
+    let is_ignored = |key: &str, keys: &[String]| {
+        keys.iter().any(|ignored| {
+            key == ignored
+                || key
+                    .strip_prefix(ignored.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    };
+    let mut unknown: Vec<String> = ignored
+        .iter()
+        .flatten()
+        .filter(|key| ignored.iter().all(|keys| is_ignored(key, keys)))
+        .cloned()
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    // Only report the outermost key, e.g., `compres` rather than everything inside of it.
+    let outermost: Vec<String> = unknown
+        .iter()
+        .filter(|key| {
+            !unknown
+                .iter()
+                .any(|other| *key != other && key.starts_with(&format!("{other}.")))
+        })
+        .cloned()
+        .collect();
+    outermost
+}
+
+fn read_config_file(path: &Path, problems: &mut Vec<String>) -> Option<toml::Table> {
+    match read_config_table(path) {
+        Ok(table) => Some(table),
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    }
+}
+
+fn report_unknown_keys(path: &Path, ignored: &[Vec<String>], problems: &mut Vec<String>) {
+    for key in find_unknown_keys(ignored) {
+        problems.push(format!("{}: unknown key '{}'", path.display(), key));
+    }
+}
+
+fn check_global_config(path: &Path, problems: &mut Vec<String>) -> Option<Config> {
+    if !path.is_file() {
+        problems.push(format!("Global config {} doesn't exist", path.display()));
+        return None;
+    }
+    let table = read_config_file(path, problems)?;
+    match parse_config::<Config>(&table) {
+        Ok((config, ignored)) => {
+            report_unknown_keys(path, &[ignored], problems);
+            Some(config)
+        }
+        Err(e) => {
+            problems.push(format!("{}: {}", path.display(), e.trim()));
+            None
+        }
+    }
+}
+
+// The retro.toml in (or above) the source has the settings for commands that work on backups.
+fn check_source_config(source: &Path, problems: &mut Vec<String>) {
+    let path = match find_file_recursively(source, "retro.toml") {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            info!("No retro.toml found for {}", source.display());
+            return;
+        }
+        Err(e) => {
+            problems.push(e.to_string());
+            return;
+        }
+    };
+    let Some(table) = read_config_file(&path, problems) else {
+        return;
+    };
+
+    let results = [
+        parse_config::<compress::Config>(&table).map(|(_, ignored)| ignored),
+        parse_config::<name::Config>(&table).map(|(_, ignored)| ignored),
+        parse_config::<playlist::Config>(&table).map(|(_, ignored)| ignored),
+    ];
+    let mut ignored = Vec::new();
+    for result in results {
+        match result {
+            Ok(keys) => ignored.push(keys),
+            Err(e) => problems.push(format!("{}: {}", path.display(), e.trim())),
+        }
+    }
+    if !ignored.is_empty() {
+        report_unknown_keys(&path, &ignored, problems);
+    }
+}
+
+fn check_system(
+    config_path: &Path,
+    name: &str,
+    system: &System,
+    sources: &[PathBuf],
+    destination: &Path,
+    problems: &mut Vec<String>,
+) -> EffectiveSystem {
+    let prefix = format!("{}: system '{}'", config_path.display(), name);
+    if system.dumper.is_empty() {
+        problems.push(format!("{prefix} doesn't have a dumper"));
+    }
+    if system.destination.is_some() && system.destinations.is_some() {
+        problems.push(format!("{prefix} has both destination and destinations"));
+    }
+    if system.extension.is_some() && system.extensions.is_some() {
+        problems.push(format!("{prefix} has both extension and extensions"));
+    }
+
+    let (system_sources, missing): (Vec<PathBuf>, Vec<PathBuf>) = system
+        .get_sources(sources, name)
+        .into_iter()
+        .partition(|system_source| system_source.is_dir());
+    if system_sources.is_empty() && !missing.is_empty() {
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        problems.push(format!(
+            "{prefix} source {} doesn't exist",
+            missing.join(", ")
+        ));
+    }
+
+    EffectiveSystem {
+        sources: system_sources,
+        destinations: system
+            .get_destinations(name)
+            .iter()
+            .map(|path| destination.join(path))
+            .collect(),
+        extensions: system.get_extensions(name),
+    }
+}
+
+fn check_destination_config(
+    sources: &[PathBuf],
+    destination: &Destination,
+    catalog: &HashMap<String, System>,
+    problems: &mut Vec<String>,
+) -> EffectiveDestination {
+    let mut effective = EffectiveDestination {
+        name: destination.name.clone(),
+        ..Default::default()
+    };
+    // A profile that lists its systems doesn't need a retro.toml.
+    let path = destination.path.join("retro.toml");
+    let table = if path.is_file() {
+        match read_config_file(&path, problems) {
+            Some(table) => table,
+            None => return effective,
+        }
+    } else if destination.systems.is_empty() {
+        problems.push(format!("{} doesn't exist", path.display()));
+        return effective;
+    } else {
+        toml::Table::new()
+    };
+    let mut config = match parse_config::<LinkDestinationConfig>(&table) {
+        Ok((config, ignored)) => {
+            report_unknown_keys(&path, &[ignored], problems);
+            config
+        }
+        Err(e) => {
+            problems.push(format!("{}: {}", path.display(), e.trim()));
+            return effective;
+        }
+    };
+    destination.apply(&mut config);
+    config.inherit(catalog);
+
+    for (name, system) in &config.systems {
+        let system = check_system(&path, name, system, sources, &destination.path, problems);
+        effective.systems.insert(name.clone(), system);
+    }
+    effective
+}
+
+// Check the global config and every retro.toml it leads to, returning everything that's wrong with
+// them along with the effective config.
+fn check_config(path: &Path) -> (Vec<String>, Option<EffectiveConfig>) {
+    let mut problems = Vec::new();
+    let Some(config) = check_global_config(path, &mut problems) else {
+        return (problems, None);
+    };
+    let link = config.link;
+
+    let (sources, destinations) = match (link.expand_sources(), link.expand_destinations()) {
+        (Ok(sources), Ok(destinations)) => (sources, destinations),
+        (Err(e), _) | (_, Err(e)) => {
+            problems.push(format!("{}: {}", path.display(), e));
+            return (problems, None);
+        }
+    };
+
+    let mut effective = EffectiveConfig::default();
+    for source in &sources {
+        if source.path.is_dir() {
+            check_source_config(&source.path, &mut problems);
+        } else {
+            problems.push(format!(
+                "Source {} ({}) doesn't exist",
+                source.name,
+                source.path.display()
+            ));
+        }
+        effective.sources.push(EffectiveSource {
+            name: source.name.clone(),
+            path: source.path.clone(),
+            priority: source.priority,
+        });
+    }
+    let sources: Vec<PathBuf> = sources.into_iter().map(|source| source.path).collect();
+    for destination in destinations {
+        if !destination.path.is_dir() {
+            problems.push(format!(
+                "Destination {} doesn't exist",
+                destination.path.display()
+            ));
+            continue;
+        }
+        let destination_config =
+            check_destination_config(&sources, &destination, &config.systems, &mut problems);
+        effective
+            .destinations
+            .insert(destination.path, destination_config);
+    }
+
+    (problems, Some(effective))
+}
+
+fn check() -> Result<(), Error> {
+    let path = get_global_config_path()?;
+    let (problems, effective) = check_config(&path);
+
+    if let Some(effective) = effective {
+        let contents = toml::to_string_pretty(&effective)
+            .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        error!("{}", contents.trim_end());
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in &problems {
+        error!("{problem}");
+    }
+    Err(Error::Config(format!(
+        "Found {} problem(s) in the config",
+        problems.len()
+    )))
+}
+
 #[cfg(test)]
 mod tests {
     use std::env::set_var;
+    use std::fs::{create_dir_all, write};
+
+    use tempdir::TempDir;
 
     use super::*;
 
@@ -171,9 +802,10 @@ mod tests {
         let config = LinkConfig {
             source: "$TEST_EXPAND_DESTINATIONS_WITH_ENVIRONMENT_VARIABLE".to_string(),
             destinations: vec![],
+            ..Default::default()
         };
-        let source = config.expand_source();
-        assert_eq!(source, PathBuf::from("a"));
+        let sources = config.expand_sources().unwrap();
+        assert_eq!(sources[0].path, PathBuf::from("a"));
     }
 
     #[test]
@@ -181,8 +813,14 @@ mod tests {
         let config = LinkConfig {
             source: "".to_string(),
             destinations: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
         };
-        let destinations = config.expand_destinations();
+        let destinations: Vec<PathBuf> = config
+            .expand_destinations()
+            .unwrap()
+            .into_iter()
+            .map(|destination| destination.path)
+            .collect();
         assert_eq!(destinations, vec![PathBuf::from("a"), PathBuf::from("b")]);
     }
 
@@ -196,8 +834,14 @@ mod tests {
                 "$TEST_EXPAND_SOURCE_WITH_ENVIROMENT_VARIABLE_1".to_string(),
                 "$TEST_EXPAND_SOURCE_WITH_ENVIROMENT_VARIABLE_2".to_string(),
             ],
+            ..Default::default()
         };
-        let destinations = config.expand_destinations();
+        let destinations: Vec<PathBuf> = config
+            .expand_destinations()
+            .unwrap()
+            .into_iter()
+            .map(|destination| destination.path)
+            .collect();
         assert_eq!(destinations, vec![PathBuf::from("a"), PathBuf::from("b")]);
     }
 
@@ -206,9 +850,148 @@ mod tests {
         let config = LinkConfig {
             source: "a".to_string(),
             destinations: vec![],
+            ..Default::default()
         };
-        let source = config.expand_source();
-        assert_eq!(source, PathBuf::from("a"));
+        let sources = config.expand_sources().unwrap();
+        assert_eq!(sources[0].path, PathBuf::from("a"));
+    }
+
+    #[test]
+    fn expand_sources_sorts_by_priority() {
+        let source = |path: &str, priority| SourceConfig {
+            path: path.to_string(),
+            priority,
+        };
+        let config = LinkConfig {
+            source: "ssd".to_string(),
+            sources: BTreeMap::from([
+                ("nas2".to_string(), source("nas2", -1)),
+                ("nas1".to_string(), source("nas1", -1)),
+                ("usb".to_string(), source("usb", 10)),
+            ]),
+            ..Default::default()
+        };
+        let sources: Vec<String> = config
+            .expand_sources()
+            .unwrap()
+            .into_iter()
+            .map(|source| source.name)
+            .collect();
+        assert_eq!(sources, vec!["usb", "default", "nas1", "nas2"]);
+    }
+
+    #[test]
+    fn expand_sources_without_sources() {
+        let error = LinkConfig::default().expand_sources().unwrap_err();
+        assert_eq!(error.exit_code(), 78);
+    }
+
+    #[test]
+    fn system_dumper_can_be_a_list() {
+        let config: LinkDestinationConfig = toml::from_str(
+            "[systems.psx]\ndumper = \"redump\"\n[systems.gba]\ndumper = [\"no-intro\", \"mine\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.systems["psx"].dumper, vec!["redump"]);
+        assert_eq!(config.systems["gba"].dumper, vec!["no-intro", "mine"]);
+        assert_eq!(
+            config.systems["gba"].get_sources(&[PathBuf::from("a"), PathBuf::from("b")], "gba"),
+            vec![
+                PathBuf::from("a/no-intro/gba"),
+                PathBuf::from("a/mine/gba"),
+                PathBuf::from("b/no-intro/gba"),
+                PathBuf::from("b/mine/gba"),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_destinations_by_name() {
+        let config = LinkConfig {
+            destinations: vec!["/mnt/sdcard".to_string()],
+            profiles: BTreeMap::from([
+                (
+                    "miyoo".to_string(),
+                    ProfileConfig {
+                        path: "/mnt/miyoo".to_string(),
+                        systems: vec!["gba".to_string()],
+                        preset: Some(Preset::Onion),
+                    },
+                ),
+                (
+                    "deck".to_string(),
+                    ProfileConfig {
+                        path: "/mnt/deck".to_string(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let names = |destinations: Vec<Destination>| -> Vec<String> {
+            destinations.into_iter().map(|d| d.name).collect()
+        };
+
+        let all = config.select_destinations(&[]).unwrap();
+        assert_eq!(names(all), vec!["/mnt/sdcard", "deck", "miyoo"]);
+
+        let miyoo = config.select_destinations(&["miyoo".to_string()]).unwrap();
+        assert_eq!(
+            miyoo,
+            vec![Destination {
+                name: "miyoo".to_string(),
+                path: PathBuf::from("/mnt/miyoo"),
+                systems: vec!["gba".to_string()],
+                preset: Some(Preset::Onion),
+            }]
+        );
+
+        let error = config
+            .select_destinations(&["miyoo".to_string(), "tv".to_string()])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Destination(s) not found in the config: tv"
+        );
+    }
+
+    #[test]
+    fn destination_apply_enables_systems() {
+        let destination = Destination {
+            name: "miyoo".to_string(),
+            path: PathBuf::from("/mnt/miyoo"),
+            systems: vec!["gba".to_string(), "psx".to_string()],
+            preset: Some(Preset::Onion),
+        };
+        let mut config: LinkDestinationConfig = toml::from_str(
+            "preset = \"minui\"\n[systems.gba]\ndestination = \"GBA\"\n[systems.snes]\n",
+        )
+        .unwrap();
+        destination.apply(&mut config);
+        assert_eq!(config.preset, Some(Preset::Minui));
+        let mut systems = config.get_system_names();
+        systems.sort();
+        assert_eq!(systems, vec!["gba", "psx"]);
+        assert_eq!(config.systems["gba"].get_destinations("gba"), vec!["GBA"]);
+    }
+
+    #[test]
+    fn expand_destinations_reports_every_unset_variable() {
+        let config = LinkConfig {
+            source: "".to_string(),
+            destinations: vec![
+                "${TEST_EXPAND_DESTINATIONS_UNSET_2}/roms".to_string(),
+                "$TEST_EXPAND_DESTINATIONS_UNSET_1".to_string(),
+                "$TEST_EXPAND_DESTINATIONS_UNSET_2".to_string(),
+            ],
+            ..Default::default()
+        };
+        let error = config.expand_destinations().unwrap_err();
+        assert_eq!(error.exit_code(), 78);
+        assert_eq!(
+            error.to_string(),
+            "Environment variable(s) not set: TEST_EXPAND_DESTINATIONS_UNSET_1, TEST_EXPAND_DESTINATIONS_UNSET_2"
+        );
     }
 
     #[test]
@@ -216,7 +999,7 @@ mod tests {
         let system1 = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
@@ -224,12 +1007,13 @@ mod tests {
         let system2 = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
         };
         let config = LinkDestinationConfig {
+            preset: None,
             systems: HashMap::from([
                 ("system1".to_string(), system1),
                 ("system2".to_string(), system2),
@@ -247,12 +1031,12 @@ mod tests {
         let system = System {
             destination: Some("a".to_string()),
             destinations: Some(destinations.to_vec()),
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
         };
-        assert_eq!(system.get_destinations(&"".to_string()), destinations);
+        assert_eq!(system.get_destinations(""), destinations);
     }
 
     #[test]
@@ -260,12 +1044,12 @@ mod tests {
         let system = System {
             destination: Some("a".to_string()),
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
         };
-        assert_eq!(system.get_destinations(&"".to_string()), &["a".to_string()]);
+        assert_eq!(system.get_destinations(""), &["a".to_string()]);
     }
 
     #[test]
@@ -273,12 +1057,12 @@ mod tests {
         let system = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
         };
-        assert_eq!(system.get_destinations(&"abc".to_string()), &["abc"]);
+        assert_eq!(system.get_destinations("abc"), &["abc"]);
     }
 
     #[test]
@@ -287,12 +1071,12 @@ mod tests {
         let system = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: Some("a".to_string()),
             extensions: Some(extensions.to_vec()),
             extra_path: None,
         };
-        assert_eq!(system.get_extensions(&"".to_string()), extensions);
+        assert_eq!(system.get_extensions(""), extensions);
     }
 
     #[test]
@@ -300,12 +1084,12 @@ mod tests {
         let system = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: Some("a".to_string()),
             extensions: None,
             extra_path: None,
         };
-        assert_eq!(system.get_extensions(&"".to_string()), &["a".to_string()]);
+        assert_eq!(system.get_extensions(""), &["a".to_string()]);
     }
 
     #[test]
@@ -313,11 +1097,261 @@ mod tests {
         let system = System {
             destination: None,
             destinations: None,
-            dumper: "".to_string(),
+            dumper: vec![],
             extension: None,
             extensions: None,
             extra_path: None,
         };
-        assert_eq!(system.get_extensions(&"abc".to_string()), &["abc"]);
+        assert_eq!(system.get_extensions("abc"), &["abc"]);
+    }
+
+    #[test]
+    fn find_unknown_keys_needs_every_config_to_ignore_a_key() {
+        let ignored = vec![
+            vec!["systems".to_string(), "compres".to_string()],
+            vec!["systems.psx.dumpr".to_string(), "compres".to_string()],
+            vec!["compres".to_string(), "systems".to_string()],
+        ];
+        assert_eq!(
+            find_unknown_keys(&ignored),
+            vec!["compres", "systems.psx.dumpr"]
+        );
+    }
+
+    #[test]
+    fn check_config_reports_problems() {
+        let root = TempDir::new("config").unwrap();
+        let source = root.path().join("source");
+        let destination = root.path().join("destination");
+        create_dir_all(source.join("no-intro").join("gba")).unwrap();
+        create_dir_all(&destination).unwrap();
+        let global = root.path().join("config.toml");
+        write(
+            &global,
+            format!(
+                "[link]\nsource = \"{}\"\ndestinations = [\"{}\", \"{}\"]\ncolor = true\n",
+                source.display(),
+                destination.display(),
+                root.path().join("missing").display(),
+            ),
+        )
+        .unwrap();
+        write(
+            destination.join("retro.toml"),
+            r#"
+[systems.gba]
+dumper = "no-intro"
+destination = "GBA"
+destinations = ["GBA"]
+
+[systems.homebrew]
+destination = "Homebrew"
+
+[systems.psx]
+dumper = "redump"
+extention = "chd"
+"#,
+        )
+        .unwrap();
+
+        let (mut problems, effective) = check_config(&global);
+        problems.sort();
+        let destination_config = destination.join("retro.toml");
+        assert_eq!(
+            problems,
+            vec![
+                format!("{}: unknown key 'link.color'", global.display()),
+                format!(
+                    "{}: system 'gba' has both destination and destinations",
+                    destination_config.display()
+                ),
+                format!(
+                    "{}: system 'homebrew' doesn't have a dumper",
+                    destination_config.display()
+                ),
+                format!(
+                    "{}: system 'psx' source {} doesn't exist",
+                    destination_config.display(),
+                    source.join("redump").join("psx").display()
+                ),
+                format!(
+                    "{}: unknown key 'systems.psx.extention'",
+                    destination_config.display()
+                ),
+                format!(
+                    "Destination {} doesn't exist",
+                    root.path().join("missing").display()
+                ),
+            ]
+        );
+
+        let effective = effective.unwrap();
+        let gba = &effective.destinations[&destination].systems["gba"];
+        assert_eq!(gba.destinations, vec![destination.join("GBA")]);
+        assert_eq!(gba.extensions, vec!["gba"]);
+    }
+
+    #[test]
+    fn system_inherit_fills_in_missing_fields() {
+        let base = System {
+            destinations: Some(vec!["PSX".to_string(), "PS1".to_string()]),
+            dumper: vec!["redump".to_string()],
+            extensions: Some(vec!["chd".to_string(), "m3u".to_string()]),
+            extra_path: Some("games".to_string()),
+            ..Default::default()
+        };
+        let mut system = System {
+            destination: Some("Sony".to_string()),
+            ..Default::default()
+        };
+        system.inherit(&base);
+        assert_eq!(system.get_destinations("psx"), vec!["Sony"]);
+        assert_eq!(system.dumper, vec!["redump"]);
+        assert_eq!(system.get_extensions("psx"), vec!["chd", "m3u"]);
+        assert_eq!(system.extra_path, Some("games".to_string()));
+    }
+
+    #[test]
+    fn load_config_file_merges_includes() {
+        let root = TempDir::new("config").unwrap();
+        create_dir_all(root.path().join("shared")).unwrap();
+        write(
+            root.path().join("shared").join("systems.toml"),
+            r#"
+[systems.gba]
+dumper = "no-intro"
+destination = "GBA"
+
+[systems.psx]
+dumper = "redump"
+"#,
+        )
+        .unwrap();
+        write(
+            root.path().join("retro.toml"),
+            r#"
+include = ["shared/systems.toml"]
+
+[systems.gba]
+extra_path = "roms"
+"#,
+        )
+        .unwrap();
+
+        let config: LinkDestinationConfig =
+            load_config_file(&root.path().join("retro.toml")).unwrap();
+        let gba = &config.systems["gba"];
+        assert_eq!(gba.dumper, vec!["no-intro"]);
+        assert_eq!(gba.destination, Some("GBA".to_string()));
+        assert_eq!(gba.extra_path, Some("roms".to_string()));
+        assert_eq!(config.systems["psx"].dumper, vec!["redump"]);
+    }
+
+    #[test]
+    fn load_config_file_rejects_include_cycles() {
+        let root = TempDir::new("config").unwrap();
+        write(root.path().join("a.toml"), "include = \"b.toml\"\n").unwrap();
+        write(root.path().join("b.toml"), "include = \"a.toml\"\n").unwrap();
+
+        let error =
+            load_config_file::<LinkDestinationConfig>(&root.path().join("a.toml")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} is included by itself",
+                root.path().join("a.toml").display()
+            )
+        );
+    }
+
+    #[test]
+    fn load_config_file_rejects_include_cycles_through_parent_directories() {
+        let root = TempDir::new("config").unwrap();
+        create_dir_all(root.path().join("x")).unwrap();
+        write(
+            root.path().join("x").join("a.toml"),
+            "include = \"../x/a.toml\"\n",
+        )
+        .unwrap();
+
+        let error =
+            load_config_file::<LinkDestinationConfig>(&root.path().join("x").join("a.toml"))
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} is included by itself",
+                root.path().join("x").join("../x/a.toml").display()
+            )
+        );
+    }
+
+    #[test]
+    fn link_destination_config_inherits_known_systems() {
+        let mut config: LinkDestinationConfig = toml::from_str(
+            r#"
+[systems]
+ps1 = {}
+gba = { destination = "GBA" }
+"#,
+        )
+        .unwrap();
+        config.inherit(&HashMap::new());
+
+        let ps1 = &config.systems["ps1"];
+        assert_eq!(ps1.dumper, vec!["redump"]);
+        assert_eq!(ps1.get_destinations("ps1"), vec!["ps1"]);
+        assert_eq!(ps1.get_extensions("ps1"), vec!["chd", "cue", "pbp", "m3u"]);
+        let gba = &config.systems["gba"];
+        assert_eq!(gba.dumper, vec!["no-intro"]);
+        assert_eq!(gba.get_destinations("gba"), vec!["GBA"]);
+    }
+
+    #[test]
+    fn link_destination_config_uses_canonical_names_from_preset() {
+        let mut config: LinkDestinationConfig = toml::from_str(
+            r#"
+preset = "es-de"
+
+[systems]
+ps1 = {}
+"#,
+        )
+        .unwrap();
+        config.inherit(&HashMap::new());
+
+        assert_eq!(config.systems["ps1"].get_destinations("ps1"), vec!["psx"]);
+    }
+
+    #[test]
+    fn link_destination_config_inherits_preset_before_catalog() {
+        let mut config: LinkDestinationConfig = toml::from_str(
+            r#"
+preset = "onion"
+
+[systems]
+psx = {}
+gba = { destination = "Advance" }
+"#,
+        )
+        .unwrap();
+        let catalog = HashMap::from([(
+            "psx".to_string(),
+            System {
+                destination: Some("PlayStation".to_string()),
+                extra_path: Some("games".to_string()),
+                ..Default::default()
+            },
+        )]);
+        config.inherit(&catalog);
+
+        let psx = &config.systems["psx"];
+        assert_eq!(psx.get_destinations("psx"), vec!["PS"]);
+        assert_eq!(psx.extra_path, Some("games".to_string()));
+        assert_eq!(psx.dumper, vec!["redump"]);
+        assert_eq!(
+            config.systems["gba"].get_destinations("gba"),
+            vec!["Advance"]
+        );
     }
 }
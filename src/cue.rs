@@ -0,0 +1,1188 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use log::{debug, error, info, warn};
+
+use regex::Regex;
+
+use super::dat::{load_from_path, Datafile};
+use super::disc::{get_referenced_files, DESCRIPTOR_EXTENSIONS};
+use super::error::Error;
+use super::utils::{find_files_with_extension, split_token};
+
+pub const FRAMES_PER_SECOND: u32 = 75;
+pub const SECONDS_PER_MINUTE: u32 = 60;
+
+// Raw data sectors start with 12 bytes of sync, followed by a 3 byte address and the mode.
+const SYNC_PATTERN: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+// Audio tracks usually start with a two second pregap.
+const PREGAP_FRAMES: u32 = 2 * FRAMES_PER_SECOND;
+
+static TRACK_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, clap::Args)]
+#[command(about = "Manage cue sheets")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    check: Option<CheckArgs>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Commands {
+    #[command(about = "Check cue sheets for problems")]
+    Check(CheckArgs),
+
+    #[command(about = "Generate cue sheets for bin files without one")]
+    Generate(GenerateArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct CheckArgs {
+    #[arg(help = "The cue sheet, or a directory containing cue sheets, to check")]
+    source: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct GenerateArgs {
+    #[arg(help = "The location to check for bin files")]
+    source: PathBuf,
+
+    #[arg(long, help = "A Redump dat file to read track information from")]
+    dat: Option<PathBuf>,
+
+    #[arg(short, long, help = "Force overwriting existing cue sheets")]
+    force: bool,
+
+    #[arg(long, help = "Don't write the cue sheets")]
+    dry_run: bool,
+}
+
+impl Args {
+    pub fn dispatch(self) -> Result<(), Error> {
+        let cmd = self
+            .command
+            .or(self.check.map(Commands::Check))
+            .ok_or_else(|| Error::from("No command provided"))?;
+        match cmd {
+            Commands::Check(args) => check_cue_sheets(args.source),
+            Commands::Generate(args) => {
+                generate_cue_sheets(args.source, args.dat, args.force, args.dry_run)
+            }
+        }
+    }
+}
+
+// A position on the disc in minutes, seconds, and frames (MM:SS:FF), stored as a frame count.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Msf(pub u32);
+
+impl Msf {
+    pub fn frames(&self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for Msf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let [minutes, seconds, frames] = parts[..] else {
+            return Err(format!("Invalid time '{}', expected MM:SS:FF", s));
+        };
+        let parse = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid time '{}', expected MM:SS:FF", s))
+        };
+        let (minutes, seconds, frames) = (parse(minutes)?, parse(seconds)?, parse(frames)?);
+        if seconds >= SECONDS_PER_MINUTE || frames >= FRAMES_PER_SECOND {
+            return Err(format!("Invalid time '{}', expected MM:SS:FF", s));
+        }
+        minutes
+            .checked_mul(SECONDS_PER_MINUTE)
+            .and_then(|total| total.checked_add(seconds))
+            .and_then(|total| total.checked_mul(FRAMES_PER_SECOND))
+            .and_then(|total| total.checked_add(frames))
+            .map(Msf)
+            .ok_or_else(|| format!("Time '{}' is too long", s))
+    }
+}
+
+impl fmt::Display for Msf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.0 % FRAMES_PER_SECOND;
+        let seconds = self.0 / FRAMES_PER_SECOND % SECONDS_PER_MINUTE;
+        let minutes = self.0 / FRAMES_PER_SECOND / SECONDS_PER_MINUTE;
+        write!(f, "{:02}:{:02}:{:02}", minutes, seconds, frames)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackMode {
+    Audio,
+    Cdg,
+    Mode1_2048,
+    Mode1_2352,
+    Mode2_2048,
+    Mode2_2324,
+    Mode2_2336,
+    Mode2_2352,
+    Cdi2336,
+    Cdi2352,
+}
+
+impl TrackMode {
+    pub fn sector_size(&self) -> u64 {
+        match self {
+            TrackMode::Audio => 2352,
+            TrackMode::Cdg => 2448,
+            TrackMode::Mode1_2048 | TrackMode::Mode2_2048 => 2048,
+            TrackMode::Mode1_2352 | TrackMode::Mode2_2352 | TrackMode::Cdi2352 => 2352,
+            TrackMode::Mode2_2324 => 2324,
+            TrackMode::Mode2_2336 | TrackMode::Cdi2336 => 2336,
+        }
+    }
+}
+
+impl FromStr for TrackMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "AUDIO" => Ok(TrackMode::Audio),
+            "CDG" => Ok(TrackMode::Cdg),
+            "MODE1/2048" => Ok(TrackMode::Mode1_2048),
+            "MODE1/2352" => Ok(TrackMode::Mode1_2352),
+            "MODE2/2048" => Ok(TrackMode::Mode2_2048),
+            "MODE2/2324" => Ok(TrackMode::Mode2_2324),
+            "MODE2/2336" => Ok(TrackMode::Mode2_2336),
+            "MODE2/2352" => Ok(TrackMode::Mode2_2352),
+            "CDI/2336" => Ok(TrackMode::Cdi2336),
+            "CDI/2352" => Ok(TrackMode::Cdi2352),
+            _ => Err(format!("Unknown track mode '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TrackMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            TrackMode::Audio => "AUDIO",
+            TrackMode::Cdg => "CDG",
+            TrackMode::Mode1_2048 => "MODE1/2048",
+            TrackMode::Mode1_2352 => "MODE1/2352",
+            TrackMode::Mode2_2048 => "MODE2/2048",
+            TrackMode::Mode2_2324 => "MODE2/2324",
+            TrackMode::Mode2_2336 => "MODE2/2336",
+            TrackMode::Mode2_2352 => "MODE2/2352",
+            TrackMode::Cdi2336 => "CDI/2336",
+            TrackMode::Cdi2352 => "CDI/2352",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueIndex {
+    pub number: u32,
+    pub position: Msf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub mode: TrackMode,
+    pub pregap: Option<Msf>,
+    pub postgap: Option<Msf>,
+    pub indexes: Vec<CueIndex>,
+    pub rems: Vec<String>,
+    // Any other commands (e.g., FLAGS or TITLE) are kept as is.
+    pub other: Vec<String>,
+}
+
+impl CueTrack {
+    pub fn new(number: u32, mode: TrackMode) -> Self {
+        Self {
+            number,
+            mode,
+            pregap: None,
+            postgap: None,
+            indexes: vec![],
+            rems: vec![],
+            other: vec![],
+        }
+    }
+
+    pub fn get_index(&self, number: u32) -> Option<Msf> {
+        self.indexes
+            .iter()
+            .find(|index| index.number == number)
+            .map(|index| index.position)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueFile {
+    pub name: String,
+    pub file_type: String,
+    // REMs and other commands that appear between the FILE and its first TRACK.
+    pub rems: Vec<String>,
+    pub other: Vec<String>,
+    pub tracks: Vec<CueTrack>,
+    // The zero-based line of the FILE command in the cue sheet it was parsed from.
+    pub line: usize,
+}
+
+impl CueFile {
+    pub fn new(name: &str, file_type: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            file_type: file_type.to_string(),
+            rems: vec![],
+            other: vec![],
+            tracks: vec![],
+            line: 0,
+        }
+    }
+
+    pub fn sector_size(&self) -> u64 {
+        self.tracks
+            .first()
+            .map_or(2352, |track| track.mode.sector_size())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CueSheet {
+    pub rems: Vec<String>,
+    // Any other commands (e.g., CATALOG or TITLE) that appear before the first FILE.
+    pub other: Vec<String>,
+    pub files: Vec<CueFile>,
+}
+
+impl CueSheet {
+    pub fn tracks(&self) -> impl Iterator<Item = &CueTrack> {
+        self.files.iter().flat_map(|file| file.tracks.iter())
+    }
+
+    // Replace the names on FILE lines while leaving everything else in the original cue sheet
+    // untouched.
+    pub fn rewrite_file_names(&self, contents: &str, names: &[String]) -> Result<String, Error> {
+        if names.len() != self.files.len() {
+            return Err(format!(
+                "Expected {} file names, got {}",
+                self.files.len(),
+                names.len()
+            )
+            .into());
+        }
+
+        let line_ending = if contents.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
+        for (file, name) in self.files.iter().zip(names) {
+            let line = lines
+                .get_mut(file.line)
+                .ok_or_else(|| format!("Line {} is missing", file.line + 1))?;
+            let indent = &line[..line.len() - line.trim_start().len()];
+            *line = format!("{}FILE \"{}\" {}", indent, name, file.file_type);
+        }
+
+        let mut rewritten = lines.join(line_ending);
+        if contents.ends_with('\n') {
+            rewritten.push_str(line_ending);
+        }
+        Ok(rewritten)
+    }
+}
+
+impl FromStr for CueSheet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sheet = CueSheet::default();
+
+        for (number, line) in s.lines().enumerate() {
+            let with_line = |e: String| format!("Line {}: {}", number + 1, e);
+            let line = line.trim();
+            let Some((command, rest)) = split_token(line) else {
+                continue;
+            };
+
+            match command.to_uppercase().as_str() {
+                "REM" => {
+                    let rem = rest.trim().to_string();
+                    match sheet.files.last_mut() {
+                        Some(file) => match file.tracks.last_mut() {
+                            Some(track) => track.rems.push(rem),
+                            None => file.rems.push(rem),
+                        },
+                        None => sheet.rems.push(rem),
+                    }
+                }
+                "FILE" => {
+                    let (name, rest) = split_token(rest)
+                        .ok_or_else(|| with_line("FILE is missing a name".to_string()))?;
+                    let (file_type, _) = split_token(rest)
+                        .ok_or_else(|| with_line("FILE is missing a type".to_string()))?;
+                    let mut file = CueFile::new(name, file_type);
+                    file.line = number;
+                    sheet.files.push(file);
+                }
+                "TRACK" => {
+                    let file = sheet
+                        .files
+                        .last_mut()
+                        .ok_or_else(|| with_line("TRACK appears before FILE".to_string()))?;
+                    let (track_number, rest) = split_token(rest)
+                        .ok_or_else(|| with_line("TRACK is missing a number".to_string()))?;
+                    let track_number = track_number.parse::<u32>().map_err(|_| {
+                        with_line(format!("Invalid track number '{}'", track_number))
+                    })?;
+                    let (mode, _) = split_token(rest)
+                        .ok_or_else(|| with_line("TRACK is missing a mode".to_string()))?;
+                    let mode = mode.parse::<TrackMode>().map_err(with_line)?;
+                    file.tracks.push(CueTrack::new(track_number, mode));
+                }
+                "INDEX" | "PREGAP" | "POSTGAP" => {
+                    let track = sheet
+                        .files
+                        .last_mut()
+                        .and_then(|file| file.tracks.last_mut())
+                        .ok_or_else(|| with_line(format!("{} appears before TRACK", command)))?;
+                    if command.eq_ignore_ascii_case("INDEX") {
+                        let (index_number, rest) = split_token(rest)
+                            .ok_or_else(|| with_line("INDEX is missing a number".to_string()))?;
+                        let index_number = index_number.parse::<u32>().map_err(|_| {
+                            with_line(format!("Invalid index number '{}'", index_number))
+                        })?;
+                        let (position, _) = split_token(rest)
+                            .ok_or_else(|| with_line("INDEX is missing a time".to_string()))?;
+                        track.indexes.push(CueIndex {
+                            number: index_number,
+                            position: position.parse().map_err(with_line)?,
+                        });
+                    } else {
+                        let (length, _) = split_token(rest)
+                            .ok_or_else(|| with_line(format!("{} is missing a time", command)))?;
+                        let length = Some(length.parse().map_err(with_line)?);
+                        if command.eq_ignore_ascii_case("PREGAP") {
+                            track.pregap = length;
+                        } else {
+                            track.postgap = length;
+                        }
+                    }
+                }
+                _ => match sheet.files.last_mut() {
+                    Some(file) => match file.tracks.last_mut() {
+                        Some(track) => track.other.push(line.to_string()),
+                        None => file.other.push(line.to_string()),
+                    },
+                    None => sheet.other.push(line.to_string()),
+                },
+            }
+        }
+
+        Ok(sheet)
+    }
+}
+
+impl fmt::Display for CueSheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rem in &self.rems {
+            writeln!(f, "REM {}", rem)?;
+        }
+        for other in &self.other {
+            writeln!(f, "{}", other)?;
+        }
+        for file in &self.files {
+            writeln!(f, "FILE \"{}\" {}", file.name, file.file_type)?;
+            for rem in &file.rems {
+                writeln!(f, "  REM {}", rem)?;
+            }
+            for other in &file.other {
+                writeln!(f, "  {}", other)?;
+            }
+            for track in &file.tracks {
+                writeln!(f, "  TRACK {:02} {}", track.number, track.mode)?;
+                for other in &track.other {
+                    writeln!(f, "    {}", other)?;
+                }
+                for rem in &track.rems {
+                    writeln!(f, "    REM {}", rem)?;
+                }
+                if let Some(pregap) = track.pregap {
+                    writeln!(f, "    PREGAP {}", pregap)?;
+                }
+                for index in &track.indexes {
+                    writeln!(f, "    INDEX {:02} {}", index.number, index.position)?;
+                }
+                if let Some(postgap) = track.postgap {
+                    writeln!(f, "    POSTGAP {}", postgap)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load_cue_sheet(path: &Path) -> Result<CueSheet, Error> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| Error::io("Failed to read cue sheet", path, e))?;
+    contents.parse().map_err(|e| {
+        Error::Parse(format!(
+            "Failed to parse cue sheet {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+// Guess the mode of a raw (2352 byte) sector from its contents.
+pub fn detect_track_mode(sector: &[u8]) -> TrackMode {
+    if sector.len() < 16 || sector[..12] != SYNC_PATTERN {
+        return TrackMode::Audio;
+    }
+    match sector[15] {
+        2 => TrackMode::Mode2_2352,
+        _ => TrackMode::Mode1_2352,
+    }
+}
+
+fn read_sector(path: &Path, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|e| Error::io("Failed to open", path, e))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| Error::io("Failed to seek in", path, e))?;
+    let mut sector = Vec::new();
+    file.take(size)
+        .read_to_end(&mut sector)
+        .map_err(|e| Error::io("Failed to read", path, e))?;
+    Ok(sector)
+}
+
+// Look for the file referenced by a cue sheet, returning the name it was actually found with if it
+// only matches when ignoring case.
+fn find_referenced_file(directory: &Path, name: &str) -> Result<Option<String>, Error> {
+    let entries = directory
+        .read_dir()
+        .map_err(|e| Error::io("Failed to read directory", directory, e))?;
+
+    let mut found = None;
+    for entry in entries.flatten() {
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+        if entry_name == name {
+            return Ok(Some(entry_name));
+        }
+        if entry_name.eq_ignore_ascii_case(name) {
+            found = Some(entry_name);
+        }
+    }
+    Ok(found)
+}
+
+pub fn check_cue_sheet(path: &Path) -> Result<Vec<String>, Error> {
+    let sheet = match load_cue_sheet(path) {
+        Ok(sheet) => sheet,
+        Err(e) => return Ok(vec![e.to_string()]),
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut problems = Vec::new();
+
+    if sheet.files.is_empty() {
+        problems.push("No FILE entries found".to_string());
+    }
+
+    let mut expected_track = 1;
+    for track in sheet.tracks() {
+        if track.number != expected_track {
+            problems.push(format!(
+                "Track {:02} is out of order, expected track {:02}",
+                track.number, expected_track
+            ));
+        }
+        expected_track = track.number + 1;
+        if track.get_index(1).is_none() {
+            problems.push(format!("Track {:02} has no INDEX 01", track.number));
+        }
+    }
+
+    for file in &sheet.files {
+        if file.tracks.is_empty() {
+            problems.push(format!("{} has no tracks", file.name));
+        }
+
+        let found_name = match find_referenced_file(directory, &file.name)? {
+            None => {
+                problems.push(format!("{} is missing", file.name));
+                continue;
+            }
+            Some(found_name) => found_name,
+        };
+        if found_name != file.name {
+            problems.push(format!(
+                "{} doesn't match the case of {}",
+                file.name, found_name
+            ));
+        }
+
+        let file_path = directory.join(&found_name);
+        let size = fs::metadata(&file_path)
+            .map_err(|e| Error::io("Failed to get metadata for", &file_path, e))?
+            .len();
+        let sector_size = file.sector_size();
+        if size % sector_size != 0 {
+            problems.push(format!(
+                "{} is {} bytes, which isn't a multiple of the {} byte sector size",
+                file.name, size, sector_size
+            ));
+        }
+
+        for track in &file.tracks {
+            let Some(start) = track.get_index(1) else {
+                continue;
+            };
+            let offset = u64::from(start.frames()) * sector_size;
+            if offset >= size {
+                problems.push(format!(
+                    "Track {:02} starts at {}, past the end of {}",
+                    track.number, start, file.name
+                ));
+                continue;
+            }
+
+            if track.mode.sector_size() != 2352 || track.mode == TrackMode::Cdi2352 {
+                continue;
+            }
+            let detected = detect_track_mode(&read_sector(&file_path, offset, 2352)?);
+            if detected != track.mode {
+                problems.push(format!(
+                    "Track {:02} is {} but its data looks like {}",
+                    track.number, track.mode, detected
+                ));
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+fn check_cue_sheets(source: PathBuf) -> Result<(), Error> {
+    let cue_sheets = if source.is_dir() {
+        let mut cue_sheets = find_files_with_extension(&source, &["cue"])?;
+        cue_sheets.sort();
+        cue_sheets
+    } else {
+        vec![source]
+    };
+    debug!("Checking {} cue sheets", cue_sheets.len());
+
+    let mut problem_count = 0;
+    for cue_sheet in &cue_sheets {
+        let problems = check_cue_sheet(cue_sheet)?;
+        if problems.is_empty() {
+            debug!("{} has no problems", cue_sheet.display());
+            continue;
+        }
+
+        warn!("{}", cue_sheet.display());
+        for problem in &problems {
+            error!("  {problem}");
+        }
+        problem_count += problems.len();
+    }
+
+    if problem_count > 0 {
+        return Err(Error::Parse(format!(
+            "Found {} problems in cue sheets",
+            problem_count
+        )));
+    }
+
+    Ok(())
+}
+
+// Split the file stem of a bin into the name of its game and its track number, e.g.,
+// `Game (Track 02)` becomes `Game` and 2.
+pub fn parse_track_name(stem: &str) -> (String, Option<u32>) {
+    let re = TRACK_PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)^(?<before>.*?)[ -]*\(?Track (?<track>\d+)\)?(?<after>.*)$")
+            .expect("Failed to compile regex pattern")
+    });
+
+    match re.captures(stem) {
+        Some(capture) => {
+            let before = capture.name("before").map_or("", |m| m.as_str());
+            let after = capture.name("after").map_or("", |m| m.as_str());
+            let track = capture
+                .name("track")
+                .and_then(|m| m.as_str().parse::<u32>().ok());
+            (format!("{before}{after}").trim().to_string(), track)
+        }
+        None => (stem.to_string(), None),
+    }
+}
+
+// A disc's data track is either raw (2352 byte sectors starting with the sync pattern) or cooked
+// (2048 byte sectors with an ISO 9660 volume descriptor in sector 16). Cartridge dumps (e.g., Mega
+// Drive or Atari 2600 games) also use .bin, but don't look like either.
+fn is_data_track(bin: &Path) -> Result<bool, Error> {
+    let size = fs::metadata(bin)
+        .map_err(|e| Error::io("Failed to get metadata for", bin, e))?
+        .len();
+    if size > 0 && size % 2352 == 0 && read_sector(bin, 0, 12)? == SYNC_PATTERN {
+        return Ok(true);
+    }
+    Ok(size > 0 && size % 2048 == 0 && read_sector(bin, 16 * 2048 + 1, 5)? == b"CD001")
+}
+
+// Find bin files that aren't referenced by any disc descriptor, grouped by the cue sheet they
+// should belong to. Groups without a data track are left out since they aren't disc images.
+pub fn find_orphaned_bins(root: &Path) -> Result<BTreeMap<PathBuf, Vec<PathBuf>>, Error> {
+    let mut referenced = HashSet::new();
+    for descriptor in find_files_with_extension(root, &DESCRIPTOR_EXTENSIONS)? {
+        match get_referenced_files(&descriptor) {
+            Ok(files) => referenced.extend(files),
+            Err(e) => warn!("{e}"),
+        }
+    }
+
+    let mut orphans: BTreeMap<PathBuf, Vec<(Option<u32>, PathBuf)>> = BTreeMap::new();
+    for bin in find_files_with_extension(root, &["bin"])? {
+        if referenced.contains(&bin) {
+            continue;
+        }
+
+        let parent = bin.parent().unwrap_or(Path::new(""));
+        let stem = bin
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", bin.display()))?;
+        let (mut game, track) = parse_track_name(stem);
+        if game.is_empty() {
+            game = parent
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("Game")
+                .to_string();
+        }
+        orphans
+            .entry(parent.join(format!("{game}.cue")))
+            .or_default()
+            .push((track, bin));
+    }
+
+    let mut discs = BTreeMap::new();
+    for (cue_sheet, mut bins) in orphans {
+        bins.sort();
+        let bins: Vec<PathBuf> = bins.into_iter().map(|(_, bin)| bin).collect();
+        let mut has_data_track = false;
+        for bin in &bins {
+            if is_data_track(bin)? {
+                has_data_track = true;
+                break;
+            }
+        }
+        if has_data_track {
+            discs.insert(cue_sheet, bins);
+        } else {
+            info!("{} has no data track. Skipping.", cue_sheet.display());
+        }
+    }
+    Ok(discs)
+}
+
+// Use a Redump dat to name the cue sheet and order its tracks. Returns None if the bins don't
+// belong to a game in the dat.
+fn match_dat(
+    dat: &Datafile,
+    cue_sheet: &Path,
+    bins: &[PathBuf],
+) -> Result<Option<(PathBuf, Vec<PathBuf>)>, Error> {
+    let Some(game) = bins
+        .iter()
+        .filter_map(|bin| bin.file_name().and_then(|name| name.to_str()))
+        .find_map(|name| dat.find_game_by_rom_name(name))
+    else {
+        return Ok(None);
+    };
+    debug!("Found {} in dat", game.name);
+
+    let parent = cue_sheet.parent().unwrap_or(Path::new(""));
+    let mut tracks = Vec::new();
+    for rom in &game.rom {
+        if !rom.name.to_lowercase().ends_with(".bin") {
+            continue;
+        }
+        let Some(bin) = bins.iter().find(|bin| {
+            bin.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.eq_ignore_ascii_case(&rom.name))
+        }) else {
+            return Err(format!("{} is missing {}", game.name, rom.name).into());
+        };
+
+        let size = fs::metadata(bin)
+            .map_err(|e| Error::io("Failed to get metadata for", bin, e))?
+            .len();
+        if size != rom.size {
+            warn!(
+                "{} is {} bytes but the dat expects {} bytes",
+                bin.display(),
+                size,
+                rom.size
+            );
+        }
+        tracks.push(bin.clone());
+    }
+
+    Ok(Some((parent.join(format!("{}.cue", game.name)), tracks)))
+}
+
+fn has_pregap(bin: &Path) -> Result<bool, Error> {
+    let size = u64::from(PREGAP_FRAMES) * 2352;
+    let pregap = read_sector(bin, 0, size)?;
+    Ok(pregap.len() as u64 == size && pregap.iter().all(|byte| *byte == 0))
+}
+
+// Build a cue sheet for bins by inspecting the layout of their sectors.
+pub fn generate_cue_sheet(bins: &[PathBuf]) -> Result<CueSheet, Error> {
+    let mut sheet = CueSheet::default();
+
+    for (i, bin) in bins.iter().enumerate() {
+        let name = bin
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", bin.display()))?;
+        let size = fs::metadata(bin)
+            .map_err(|e| Error::io("Failed to get metadata for", bin, e))?
+            .len();
+
+        let mode = if bins.len() == 1 && size % 2352 != 0 && size % 2048 == 0 {
+            TrackMode::Mode1_2048
+        } else {
+            detect_track_mode(&read_sector(bin, 0, 2352)?)
+        };
+
+        let mut track = CueTrack::new(i as u32 + 1, mode);
+        if mode == TrackMode::Audio && i > 0 && has_pregap(bin)? {
+            track.indexes.push(CueIndex {
+                number: 0,
+                position: Msf(0),
+            });
+            track.indexes.push(CueIndex {
+                number: 1,
+                position: Msf(PREGAP_FRAMES),
+            });
+        } else {
+            track.indexes.push(CueIndex {
+                number: 1,
+                position: Msf(0),
+            });
+        }
+
+        let mut file = CueFile::new(name, "BINARY");
+        file.tracks.push(track);
+        sheet.files.push(file);
+    }
+
+    Ok(sheet)
+}
+
+fn generate_cue_sheets(
+    source: PathBuf,
+    dat: Option<PathBuf>,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    debug!("Generating cue sheets for bin files in {source:?}");
+
+    let dat = dat.map(|path| load_from_path(&path)).transpose()?;
+
+    for (cue_sheet, bins) in find_orphaned_bins(&source)? {
+        let (cue_sheet, bins) = match dat {
+            Some(ref dat) => match match_dat(dat, &cue_sheet, &bins) {
+                Ok(Some(matched)) => matched,
+                Ok(None) => {
+                    info!("{} not found in dat", cue_sheet.display());
+                    (cue_sheet, bins)
+                }
+                Err(e) => {
+                    error!("{e}. Skipping.");
+                    continue;
+                }
+            },
+            None => (cue_sheet, bins),
+        };
+
+        if !force && cue_sheet.exists() {
+            warn!("{} exists. Skipping.", cue_sheet.display());
+            continue;
+        }
+
+        let sheet = generate_cue_sheet(&bins)?;
+        if dry_run {
+            error!("Would generate {cue_sheet:?}");
+            debug!("{sheet}");
+            continue;
+        }
+
+        error!("Generating {cue_sheet:?}");
+        fs::write(&cue_sheet, sheet.to_string())
+            .map_err(|e| Error::io("Failed to write cue sheet", &cue_sheet, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Self {
+            Self {
+                root: TempDir::new("tmp").unwrap(),
+            }
+        }
+
+        fn teardown(self) {
+            self.root.close().unwrap();
+        }
+    }
+
+    const MULTI_TRACK: &str = r#"REM COMMENT "Dumped"
+CATALOG 0000000000000
+FILE "Game (Track 1).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Game (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    FLAGS DCP
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+"#;
+
+    fn data_sector(mode: u8) -> Vec<u8> {
+        let mut sector = vec![0; 2352];
+        sector[..12].copy_from_slice(&SYNC_PATTERN);
+        sector[15] = mode;
+        sector
+    }
+
+    #[test]
+    fn msf_round_trip() {
+        let msf: Msf = "01:02:03".parse().unwrap();
+        assert_eq!(msf.frames(), (60 + 2) * 75 + 3);
+        assert_eq!(msf.to_string(), "01:02:03");
+    }
+
+    #[test]
+    fn msf_rejects_invalid_frames() {
+        assert!("00:00:75".parse::<Msf>().is_err());
+        assert!("00:00".parse::<Msf>().is_err());
+    }
+
+    #[test]
+    fn msf_rejects_overflowing_times() {
+        assert_eq!(
+            "99999999:00:00".parse::<Msf>().unwrap_err(),
+            "Time '99999999:00:00' is too long"
+        );
+    }
+
+    #[test]
+    fn parse_multi_track_cue_sheet() {
+        let sheet: CueSheet = MULTI_TRACK.parse().unwrap();
+        assert_eq!(sheet.rems, &["COMMENT \"Dumped\""]);
+        assert_eq!(sheet.other, &["CATALOG 0000000000000"]);
+        assert_eq!(sheet.files.len(), 2);
+
+        let file = &sheet.files[1];
+        assert_eq!(file.name, "Game (Track 2).bin");
+        assert_eq!(file.file_type, "BINARY");
+        assert_eq!(file.line, 5);
+
+        let track = &file.tracks[0];
+        assert_eq!(track.number, 2);
+        assert_eq!(track.mode, TrackMode::Audio);
+        assert_eq!(track.other, &["FLAGS DCP"]);
+        assert_eq!(track.get_index(0), Some(Msf(0)));
+        assert_eq!(track.get_index(1), Some(Msf(150)));
+    }
+
+    #[test]
+    fn cue_sheet_round_trip_keeps_rems_in_place() {
+        let contents = "REM GENRE Game
+CATALOG 0000000000000
+FILE \"Game (Track 1).bin\" BINARY
+  REM SESSION 01
+  TRACK 01 MODE1/2352
+    REM ORIGINAL MEDIA-TYPE: CD
+    INDEX 01 00:00:00
+FILE \"Game (Track 2).bin\" BINARY
+  REM SESSION 02
+  TRACK 02 AUDIO
+    FLAGS DCP
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+";
+        let sheet: CueSheet = contents.parse().unwrap();
+        assert_eq!(sheet.rems, &["GENRE Game"]);
+        assert_eq!(sheet.files[1].rems, &["SESSION 02"]);
+        assert_eq!(sheet.to_string(), contents);
+    }
+
+    #[test]
+    fn parse_unquoted_lowercase_file() {
+        let sheet: CueSheet = "file game.bin BINARY\n".parse().unwrap();
+        assert_eq!(sheet.files[0].name, "game.bin");
+    }
+
+    #[test]
+    fn parse_track_before_file() {
+        let error = "TRACK 01 AUDIO\n".parse::<CueSheet>().unwrap_err();
+        assert_eq!(error, "Line 1: TRACK appears before FILE");
+    }
+
+    #[test]
+    fn parse_unknown_track_mode() {
+        let error = "FILE \"a.bin\" BINARY\n  TRACK 01 MODE3/2352\n"
+            .parse::<CueSheet>()
+            .unwrap_err();
+        assert_eq!(error, "Line 2: Unknown track mode 'MODE3/2352'");
+    }
+
+    #[test]
+    fn display_cue_sheet() {
+        let mut file = CueFile::new("Game.bin", "BINARY");
+        let mut track = CueTrack::new(1, TrackMode::Mode1_2352);
+        track.pregap = Some(Msf(150));
+        track.indexes.push(CueIndex {
+            number: 1,
+            position: Msf(0),
+        });
+        file.tracks.push(track);
+        let sheet = CueSheet {
+            files: vec![file],
+            ..Default::default()
+        };
+        assert_eq!(
+            sheet.to_string(),
+            "FILE \"Game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    PREGAP 00:02:00\n    INDEX 01 00:00:00\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_file_names_only_changes_file_lines() {
+        let contents = MULTI_TRACK.replace("CATALOG 0000000000000", "TITLE \"Game\"");
+        let sheet: CueSheet = contents.parse().unwrap();
+        let rewritten = sheet
+            .rewrite_file_names(
+                &contents,
+                &[
+                    "New (Track 1).bin".to_string(),
+                    "New (Track 2).bin".to_string(),
+                ],
+            )
+            .unwrap();
+        assert_eq!(rewritten, contents.replace("FILE \"Game", "FILE \"New"));
+    }
+
+    #[test]
+    fn detect_track_mode_from_sector() {
+        assert_eq!(detect_track_mode(&data_sector(1)), TrackMode::Mode1_2352);
+        assert_eq!(detect_track_mode(&data_sector(2)), TrackMode::Mode2_2352);
+        assert_eq!(detect_track_mode(&[0; 2352]), TrackMode::Audio);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn check_cue_sheet_without_problems(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), MULTI_TRACK).unwrap();
+        write(root.join("Game (Track 1).bin"), data_sector(2)).unwrap();
+        write(root.join("Game (Track 2).bin"), vec![0; 2352 * 151]).unwrap();
+
+        let problems = check_cue_sheet(&root.join("Game.cue")).unwrap();
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn check_cue_sheet_with_problems(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), MULTI_TRACK.replace("MODE2", "MODE1")).unwrap();
+        write(root.join("game (track 1).bin"), data_sector(2)).unwrap();
+
+        let problems = check_cue_sheet(&root.join("Game.cue")).unwrap();
+        assert_eq!(
+            problems,
+            &[
+                "Game (Track 1).bin doesn't match the case of game (track 1).bin",
+                "Track 01 is MODE1/2352 but its data looks like MODE2/2352",
+                "Game (Track 2).bin is missing",
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn check_cue_sheet_with_bad_size(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(
+            root.join("Game.cue"),
+            "FILE \"Game.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        write(root.join("Game.bin"), vec![0; 2000]).unwrap();
+
+        let problems = check_cue_sheet(&root.join("Game.cue")).unwrap();
+        assert_eq!(
+            problems,
+            &["Game.bin is 2000 bytes, which isn't a multiple of the 2352 byte sector size"]
+        );
+    }
+
+    #[test]
+    fn parse_track_name_with_track() {
+        assert_eq!(
+            parse_track_name("Game (USA) (Track 02)"),
+            ("Game (USA)".to_string(), Some(2))
+        );
+        assert_eq!(
+            parse_track_name("Game (Track 1) (Rev 1)"),
+            ("Game (Rev 1)".to_string(), Some(1))
+        );
+        assert_eq!(parse_track_name("Track 03"), ("".to_string(), Some(3)));
+    }
+
+    #[test]
+    fn parse_track_name_without_track() {
+        assert_eq!(
+            parse_track_name("Game (USA)"),
+            ("Game (USA)".to_string(), None)
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_orphaned_bins_skips_referenced_bins(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), "FILE \"Game.bin\" BINARY\n").unwrap();
+        write(root.join("Game.bin"), "").unwrap();
+        write(root.join("Other (Track 2).bin"), "").unwrap();
+        write(root.join("Other (Track 10).bin"), "").unwrap();
+        write(root.join("Other (Track 1).bin"), data_sector(1)).unwrap();
+        write(
+            root.join("Dreamcast.gdi"),
+            "2\n1 0 4 2352 track01.bin 0\n2 600 0 2352 track02.bin 0\n",
+        )
+        .unwrap();
+        write(root.join("track01.bin"), data_sector(1)).unwrap();
+        write(root.join("track02.bin"), data_sector(1)).unwrap();
+        write(root.join("Sonic.bin"), vec![0; 2048 * 256]).unwrap();
+
+        let orphans = find_orphaned_bins(root).unwrap();
+        assert_eq!(
+            orphans,
+            BTreeMap::from([(
+                root.join("Other.cue"),
+                vec![
+                    root.join("Other (Track 1).bin"),
+                    root.join("Other (Track 2).bin"),
+                    root.join("Other (Track 10).bin"),
+                ]
+            )])
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_cue_sheet_from_sectors(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let track1 = root.join("Game (Track 1).bin");
+        let track2 = root.join("Game (Track 2).bin");
+        write(&track1, data_sector(2)).unwrap();
+        write(&track2, vec![0; 2352 * 200]).unwrap();
+
+        let sheet = generate_cue_sheet(&[track1, track2]).unwrap();
+        assert_eq!(
+            sheet.to_string(),
+            r#"FILE "Game (Track 1).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Game (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+"#
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_cue_sheet_for_cooked_sectors(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let bin = root.join("Game.bin");
+        write(&bin, vec![0; 2048 * 3]).unwrap();
+
+        let sheet = generate_cue_sheet(&[bin]).unwrap();
+        assert_eq!(sheet.files[0].tracks[0].mode, TrackMode::Mode1_2048);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn match_dat_orders_tracks(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a (Track 1).bin"), vec![0; 4]).unwrap();
+        write(root.join("a (Track 2).bin"), vec![0; 4]).unwrap();
+        let dat = crate::dat::load_from_string(
+            r#"<?xml version="1.0"?>
+            <datafile>
+                <header><name>Test</name><version>1</version></header>
+                <game name="Game (USA)">
+                    <rom name="a (Track 2).bin" size="4" crc="0" md5="0" sha1="0"/>
+                    <rom name="Game (USA).cue" size="4" crc="0" md5="0" sha1="0"/>
+                    <rom name="a (Track 1).bin" size="4" crc="0" md5="0" sha1="0"/>
+                </game>
+            </datafile>"#
+                .to_string(),
+        )
+        .unwrap();
+
+        let bins = vec![root.join("a (Track 1).bin"), root.join("a (Track 2).bin")];
+        let (cue_sheet, tracks) = match_dat(&dat, &root.join("a.cue"), &bins)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cue_sheet, root.join("Game (USA).cue"));
+        assert_eq!(
+            tracks,
+            vec![root.join("a (Track 2).bin"), root.join("a (Track 1).bin")]
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use std::fs;
+use std::path::Path;
+
 use serde::{Deserialize, Serialize};
 
+use super::error::Error;
+
 // Initially generated using https://thomblin.github.io/xml_schema_generator/.
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct Datafile {
@@ -9,7 +14,7 @@ pub struct Datafile {
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct Header {
-    pub id: u32,
+    pub id: Option<u32>,
     pub name: String,
     pub version: String,
 }
@@ -19,8 +24,9 @@ pub struct Game {
     #[serde(rename = "@name")]
     pub name: String,
     #[serde(rename = "@id")]
-    pub id: String,
-    pub rom: Rom,
+    pub id: Option<String>,
+    // No-Intro games have a single rom, Redump games have one for each track and the cue sheet.
+    pub rom: Vec<Rom>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -28,7 +34,7 @@ pub struct Rom {
     #[serde(rename = "@name")]
     pub name: String,
     #[serde(rename = "@size")]
-    pub size: u32,
+    pub size: u64,
     #[serde(rename = "@crc")]
     pub crc: String,
     #[serde(rename = "@md5")]
@@ -36,13 +42,31 @@ pub struct Rom {
     #[serde(rename = "@sha1")]
     pub sha1: String,
     #[serde(rename = "@sha256")]
-    pub sha256: String,
+    pub sha256: Option<String>,
     #[serde(rename = "@status")]
     pub status: Option<String>,
 }
 
-pub fn load_from_string(xml: String) -> Result<Datafile, String> {
-    serde_xml_rs::from_str(&xml).map_err(|e| format!("Failed to parse XML dat file: {}", e))
+impl Datafile {
+    // Find the game that a file belongs to by the name of one of its roms.
+    pub fn find_game_by_rom_name(&self, name: &str) -> Option<&Game> {
+        self.game.iter().find(|game| {
+            game.rom
+                .iter()
+                .any(|rom| rom.name.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+pub fn load_from_path(path: &Path) -> Result<Datafile, Error> {
+    let xml =
+        fs::read_to_string(path).map_err(|e| Error::io("Failed to read dat file", path, e))?;
+    load_from_string(xml)
+}
+
+pub fn load_from_string(xml: String) -> Result<Datafile, Error> {
+    serde_xml_rs::from_str(&xml)
+        .map_err(|e| Error::Parse(format!("Failed to parse XML dat file: {}", e)))
 }
 
 #[cfg(test)]
@@ -65,7 +89,7 @@ mod tests {
             "#;
 
         let dat = load_from_string(xml.to_string()).unwrap();
-        assert_eq!(dat.header.id, 1);
+        assert_eq!(dat.header.id, Some(1));
         assert_eq!(dat.header.name, "Test System");
         assert_eq!(dat.header.version, "000000");
 
@@ -73,15 +97,15 @@ mod tests {
 
         let game = &dat.game[0];
         assert_eq!(game.name, "Test Game");
-        assert_eq!(game.id, "0001");
-        assert_eq!(game.rom.name, "Test Game.ext");
-        assert_eq!(game.rom.size, 40976);
-        assert_eq!(game.rom.crc, "393a432f");
-        assert_eq!(game.rom.md5, "f94bb9bb55f325d9af8a0fff80b9376d");
-        assert_eq!(game.rom.sha1, "33d23c2f2cfa4c9efec87f7bc1321ce3ce6c89bd");
+        assert_eq!(game.id, Some("0001".to_string()));
+        assert_eq!(game.rom[0].name, "Test Game.ext");
+        assert_eq!(game.rom[0].size, 40976);
+        assert_eq!(game.rom[0].crc, "393a432f");
+        assert_eq!(game.rom[0].md5, "f94bb9bb55f325d9af8a0fff80b9376d");
+        assert_eq!(game.rom[0].sha1, "33d23c2f2cfa4c9efec87f7bc1321ce3ce6c89bd");
         assert_eq!(
-            game.rom.sha256,
-            "0b3d9e1f01ed1668205bab34d6c82b0e281456e137352e4f36a9b2cfa3b66dea"
+            game.rom[0].sha256,
+            Some("0b3d9e1f01ed1668205bab34d6c82b0e281456e137352e4f36a9b2cfa3b66dea".to_string())
         );
     }
 
@@ -104,7 +128,7 @@ mod tests {
             "#;
 
         let dat = load_from_string(xml.to_string()).unwrap();
-        assert_eq!(dat.header.id, 1);
+        assert_eq!(dat.header.id, Some(1));
         assert_eq!(dat.header.name, "Test System");
         assert_eq!(dat.header.version, "000000");
 
@@ -112,19 +136,59 @@ mod tests {
 
         let game1 = &dat.game[0];
         assert_eq!(game1.name, "Test Game");
-        assert_eq!(game1.rom.name, "Test Game.ext");
+        assert_eq!(game1.rom[0].name, "Test Game.ext");
 
         let game2 = &dat.game[1];
         assert_eq!(game2.name, "Test Game 2");
-        assert_eq!(game2.id, "0002");
-        assert_eq!(game2.rom.name, "Test Game 2.ext");
-        assert_eq!(game2.rom.size, 262160);
-        assert_eq!(game2.rom.crc, "43507232");
-        assert_eq!(game2.rom.md5, "55f7030dc6173f2a0145a97f369f49f4");
-        assert_eq!(game2.rom.sha1, "d3f8cfd7822c1cf634c2132009f877b44244850f");
+        assert_eq!(game2.id, Some("0002".to_string()));
+        assert_eq!(game2.rom[0].name, "Test Game 2.ext");
+        assert_eq!(game2.rom[0].size, 262160);
+        assert_eq!(game2.rom[0].crc, "43507232");
+        assert_eq!(game2.rom[0].md5, "55f7030dc6173f2a0145a97f369f49f4");
+        assert_eq!(
+            game2.rom[0].sha1,
+            "d3f8cfd7822c1cf634c2132009f877b44244850f"
+        );
         assert_eq!(
-            game2.rom.sha256,
-            "41300bc4942a8a4f9b53148b404dd5cae3dd708ebdd9b617888d290a51a83e43"
+            game2.rom[0].sha256,
+            Some("41300bc4942a8a4f9b53148b404dd5cae3dd708ebdd9b617888d290a51a83e43".to_string())
         );
     }
+
+    #[test]
+    fn parse_redump_game_with_tracks() {
+        let xml = r#"<?xml version="1.0"?>
+            <datafile>
+                <header>
+                    <name>Sony - PlayStation</name>
+                    <description>Sony - PlayStation</description>
+                    <version>2024-01-01 00-00-00</version>
+                </header>
+                <game name="Test Game (USA)">
+                    <category>Games</category>
+                    <description>Test Game (USA)</description>
+                    <rom name="Test Game (USA).cue" size="214" crc="00000000" md5="00000000000000000000000000000000" sha1="0000000000000000000000000000000000000000"/>
+                    <rom name="Test Game (USA) (Track 1).bin" size="4704" crc="00000000" md5="00000000000000000000000000000000" sha1="0000000000000000000000000000000000000000"/>
+                    <rom name="Test Game (USA) (Track 2).bin" size="352800" crc="00000000" md5="00000000000000000000000000000000" sha1="0000000000000000000000000000000000000000"/>
+                </game>
+            </datafile>
+            "#;
+
+        let dat = load_from_string(xml.to_string()).unwrap();
+        assert_eq!(dat.header.id, None);
+        assert_eq!(dat.game.len(), 1);
+
+        let game = &dat.game[0];
+        assert_eq!(game.id, None);
+        assert_eq!(game.rom.len(), 3);
+        assert_eq!(game.rom[2].name, "Test Game (USA) (Track 2).bin");
+        assert_eq!(game.rom[2].size, 352800);
+        assert_eq!(game.rom[2].sha256, None);
+
+        let found = dat
+            .find_game_by_rom_name("test game (usa) (track 1).bin")
+            .unwrap();
+        assert_eq!(found.name, "Test Game (USA)");
+        assert!(dat.find_game_by_rom_name("Other Game.bin").is_none());
+    }
 }
@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use super::cue::CueSheet;
+use super::error::Error;
+use super::utils::{find_files_with_extension, split_token};
+
+// Formats that describe a disc by referencing one or more track files. When a descriptor is found,
+// the files it references are part of the same logical disc and shouldn't be handled on their own.
+pub const DESCRIPTOR_EXTENSIONS: [&str; 3] = ["cue", "gdi", "toc"];
+
+// Discs that belong to a playlist can be kept out of sight of frontends, either in a hidden
+// directory next to the playlist or in a directory named after the playlist (e.g., `Game.m3u/`).
+pub const HIDDEN_DISC_DIRECTORY: &str = ".discs";
+
+#[derive(Debug, PartialEq)]
+pub struct Disc {
+    pub path: PathBuf,
+    pub tracks: Vec<PathBuf>,
+}
+
+impl Disc {
+    pub fn missing_tracks(&self) -> Vec<&PathBuf> {
+        self.tracks
+            .iter()
+            .filter(|track| !track.is_file())
+            .collect()
+    }
+}
+
+pub fn is_descriptor(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| DESCRIPTOR_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+pub fn is_disc_directory(path: &Path) -> bool {
+    path.is_dir()
+        && (path.file_name() == Some(HIDDEN_DISC_DIRECTORY.as_ref())
+            || path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u")))
+}
+
+// Find the disc directory below root that a file is in, if any.
+pub fn get_disc_directory<'a>(root: &Path, path: &'a Path) -> Option<&'a Path> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(root) && *ancestor != root)
+        .find(|ancestor| is_disc_directory(ancestor))
+}
+
+// Find every logical disc under root. Files referenced by a descriptor are folded into that
+// descriptor's disc and images that share a name with a descriptor in the same directory (e.g., a
+// cue and an iso dumped from the same game) are only returned once, preferring the descriptor.
+pub fn find_discs(root: &Path, extensions: &[&str]) -> Result<Vec<Disc>, Error> {
+    let mut files = find_files_with_extension(root, extensions)?;
+    files.sort();
+
+    let mut discs = Vec::new();
+    let mut referenced = HashSet::new();
+    for file in files.iter().filter(|file| is_descriptor(file)) {
+        let tracks = match get_referenced_files(file) {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                warn!("{e}. Skipping.");
+                continue;
+            }
+        };
+        referenced.extend(tracks.iter().cloned());
+        discs.push(Disc {
+            path: file.clone(),
+            tracks,
+        });
+    }
+    for file in files.iter().filter(|file| !is_descriptor(file)) {
+        if referenced.contains(file) {
+            debug!(
+                "{} is referenced by a descriptor. Skipping.",
+                file.display()
+            );
+            continue;
+        }
+        discs.push(Disc {
+            path: file.clone(),
+            tracks: vec![file.clone()],
+        });
+    }
+
+    let mut unique: BTreeMap<PathBuf, Disc> = BTreeMap::new();
+    for disc in discs {
+        let key = disc.path.with_extension("");
+        if let Some(existing) = unique.get(&key) {
+            warn!(
+                "{} is the same disc as {}. Skipping.",
+                disc.path.display(),
+                existing.path.display()
+            );
+            continue;
+        }
+        unique.insert(key, disc);
+    }
+
+    Ok(unique.into_values().collect())
+}
+
+pub fn get_referenced_files(descriptor: &Path) -> Result<Vec<PathBuf>, Error> {
+    let contents =
+        fs::read_to_string(descriptor).map_err(|e| Error::io("Failed to read", descriptor, e))?;
+    let parent = descriptor.parent().unwrap_or(Path::new(""));
+
+    let extension = descriptor
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    let names = match extension.as_deref() {
+        Some("cue") => contents
+            .parse::<CueSheet>()
+            .map_err(|e| Error::Parse(format!("Failed to parse {}: {}", descriptor.display(), e)))?
+            .files
+            .into_iter()
+            .map(|file| file.name)
+            .collect(),
+        Some("gdi") => parse_gdi_files(&contents),
+        Some("toc") => parse_toc_files(&contents),
+        _ => return Err(format!("{} is not a disc descriptor", descriptor.display()).into()),
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for name in names {
+        let file = parent.join(name);
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+// A gdi file starts with the number of tracks, followed by one line per track:
+// `<track> <lba> <type> <sector size> <file name> <offset>`.
+fn parse_gdi_files(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut rest = line;
+            for _ in 0..4 {
+                (_, rest) = split_token(rest)?;
+            }
+            let (name, _) = split_token(rest)?;
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+fn parse_toc_files(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (command, rest) = split_token(line)?;
+            if !(command == "FILE" || command == "AUDIOFILE" || command == "DATAFILE") {
+                return None;
+            }
+            let (name, _) = split_token(rest)?;
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{write, File};
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Self {
+            Self {
+                root: TempDir::new("tmp").unwrap(),
+            }
+        }
+
+        fn teardown(self) {
+            self.root.close().unwrap();
+        }
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn get_disc_directory_finds_hidden_and_playlist_directories(ctx: &mut Context) {
+        let root = ctx.root.path();
+        fs::create_dir_all(root.join(".discs")).unwrap();
+        fs::create_dir_all(root.join("Game.m3u")).unwrap();
+
+        let hidden = root.join(".discs").join("Game (Disc 1).chd");
+        assert_eq!(
+            get_disc_directory(root, &hidden),
+            Some(root.join(".discs").as_path())
+        );
+        let folder = root.join("Game.m3u").join("Game (Disc 1).chd");
+        assert_eq!(
+            get_disc_directory(root, &folder),
+            Some(root.join("Game.m3u").as_path())
+        );
+        assert_eq!(get_disc_directory(root, &root.join("Game.chd")), None);
+        assert_eq!(get_disc_directory(&root.join(".discs"), &hidden), None);
+    }
+
+    #[test]
+    fn parse_gdi_files_with_quoted_and_unquoted_names() {
+        let gdi = r#"3
+1 0 4 2352 track01.bin 0
+2 756 0 2352 "Game (Track 2).raw" 0
+3 45000 4 2352 track03.bin 0
+"#;
+        assert_eq!(
+            parse_gdi_files(gdi),
+            &["track01.bin", "Game (Track 2).raw", "track03.bin"]
+        );
+    }
+
+    #[test]
+    fn parse_toc_files_with_data_and_audio() {
+        let toc = r#"CD_ROM
+TRACK MODE1
+DATAFILE "data.bin" 00:10:00
+TRACK AUDIO
+FILE "audio.bin" 0 02:00:00
+"#;
+        assert_eq!(parse_toc_files(toc), &["data.bin", "audio.bin"]);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_discs_groups_tracks_with_descriptor(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(
+            root.join("Game.cue"),
+            "FILE \"Game (Track 1).bin\" BINARY\nFILE \"Game (Track 2).bin\" BINARY\n",
+        )
+        .unwrap();
+        File::create(root.join("Game (Track 1).bin")).unwrap();
+        File::create(root.join("Game (Track 2).bin")).unwrap();
+
+        let discs = find_discs(root, &["bin", "cue"]).unwrap();
+        assert_eq!(
+            discs,
+            vec![Disc {
+                path: root.join("Game.cue"),
+                tracks: vec![
+                    root.join("Game (Track 1).bin"),
+                    root.join("Game (Track 2).bin")
+                ],
+            }]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_discs_prefers_descriptor_over_image(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), "FILE \"Game.bin\" BINARY\n").unwrap();
+        File::create(root.join("Game.bin")).unwrap();
+        File::create(root.join("Game.iso")).unwrap();
+        File::create(root.join("Other.iso")).unwrap();
+
+        let discs = find_discs(root, &["cue", "iso"]).unwrap();
+        let paths: Vec<&PathBuf> = discs.iter().map(|disc| &disc.path).collect();
+        assert_eq!(paths, vec![&root.join("Game.cue"), &root.join("Other.iso")]);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_discs_skips_unreadable_descriptors(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Bad.cue"), [0xff, 0xfe, 0xfd]).unwrap();
+        write(root.join("Game.cue"), "FILE \"Game.bin\" BINARY\n").unwrap();
+        File::create(root.join("Game.bin")).unwrap();
+
+        let discs = find_discs(root, &["bin", "cue"]).unwrap();
+        let paths: Vec<&PathBuf> = discs.iter().map(|disc| &disc.path).collect();
+        assert_eq!(paths, vec![&root.join("Game.cue")]);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn disc_missing_tracks(ctx: &mut Context) {
+        let root = ctx.root.path();
+        File::create(root.join("Game (Track 1).bin")).unwrap();
+        let disc = Disc {
+            path: root.join("Game.cue"),
+            tracks: vec![
+                root.join("Game (Track 1).bin"),
+                root.join("Game (Track 2).bin"),
+            ],
+        };
+        assert_eq!(
+            disc.missing_tracks(),
+            vec![&root.join("Game (Track 2).bin")]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Everything that can go wrong while running a command. Each kind of error exits with its own code
+// (see `exit_code`) so that scripts can tell them apart.
+#[derive(Debug)]
+pub enum Error {
+    // Reading or writing a file or directory failed.
+    Io {
+        message: String,
+        path: PathBuf,
+        source: io::Error,
+    },
+    // A config file couldn't be found or loaded, or contains an invalid value.
+    Config(String),
+    // An external command ran but failed.
+    Command {
+        command: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    // An external command isn't installed.
+    MissingTool(String),
+    // A file (e.g., a cue sheet or a dat) couldn't be parsed.
+    Parse(String),
+    // Some of the work failed. The individual failures have already been logged.
+    Partial(String),
+    // Anything else, e.g., a file that's in the way.
+    Other(String),
+}
+
+impl Error {
+    pub fn io(message: &str, path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            message: message.to_string(),
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    // Exit codes follow sysexits.h where there's a match.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Other(_) => 1,
+            Self::Partial(_) => 3,
+            Self::Parse(_) => 65,
+            Self::MissingTool(_) => 69,
+            Self::Command { .. } => 70,
+            Self::Io { .. } => 74,
+            Self::Config(_) => 78,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io {
+                message,
+                path,
+                source,
+            } => write!(f, "{} {}: {}", message, path.display(), source),
+            Self::Command {
+                command,
+                status,
+                stderr,
+            } => {
+                match status {
+                    Some(code) => write!(f, "{} failed with exit code {}", command, code)?,
+                    None => write!(f, "{} was terminated by a signal", command)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+            Self::MissingTool(command) => write!(f, "Failed to find command '{}' in PATH", command),
+            Self::Config(message)
+            | Self::Parse(message)
+            | Self::Partial(message)
+            | Self::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Most errors are only ever shown to the user, so a message is enough to create one.
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_display() {
+        let error = Error::io(
+            "Failed to read",
+            Path::new("game.cue"),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(error.to_string(), "Failed to read game.cue: not found");
+
+        let error = Error::Command {
+            command: "chdman".to_string(),
+            status: Some(1),
+            stderr: "Error: file not found".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "chdman failed with exit code 1: Error: file not found"
+        );
+    }
+
+    #[test]
+    fn error_exit_codes_are_distinct() {
+        let errors = [
+            Error::Other(String::new()),
+            Error::Partial(String::new()),
+            Error::Parse(String::new()),
+            Error::MissingTool(String::new()),
+            Error::Command {
+                command: String::new(),
+                status: None,
+                stderr: String::new(),
+            },
+            Error::io("", Path::new(""), io::Error::other("")),
+            Error::Config(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+}
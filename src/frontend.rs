@@ -0,0 +1,440 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+use super::config::System;
+use super::error::Error;
+use super::name::get_display_name;
+use super::systems::KnownSystem;
+
+// A hint for which RetroArch core should run a playlist's games.
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Core {
+    pub path: String,
+    pub name: String,
+}
+
+// The folder names and extensions a frontend expects. EmulationStation-based frontends use the
+// built-in systems' names, RetroArch uses the full names that its databases and thumbnails use, and
+// OnionOS and MinUI have their own names.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Batocera,
+    EsDe,
+    Minui,
+    Onion,
+    Retroarch,
+}
+
+const ONION_FOLDERS: [(&str, &str); 30] = [
+    ("3do", "PANASONIC"),
+    ("amiga", "AMIGA"),
+    ("amstradcpc", "CPC"),
+    ("atari2600", "ATARI"),
+    ("atari7800", "SEVENTYEIGHTHUNDRED"),
+    ("atarilynx", "LYNX"),
+    ("c64", "COMMODORE"),
+    ("dreamcast", "DC"),
+    ("gamegear", "GG"),
+    ("gb", "GB"),
+    ("gba", "GBA"),
+    ("gbc", "GBC"),
+    ("mastersystem", "MS"),
+    ("megadrive", "MD"),
+    ("n64", "N64"),
+    ("nds", "NDS"),
+    ("neogeocd", "NEOCD"),
+    ("nes", "FC"),
+    ("ngp", "NGP"),
+    ("ngpc", "NGP"),
+    ("pcengine", "PCE"),
+    ("pcenginecd", "PCECD"),
+    ("pcfx", "PCFX"),
+    ("psp", "PSP"),
+    ("psx", "PS"),
+    ("saturn", "SATURN"),
+    ("sega32x", "THIRTYTWOX"),
+    ("segacd", "SEGACD"),
+    ("snes", "SFC"),
+    ("virtualboy", "VB"),
+];
+
+// MinUI finds emulators by the tag at the end of a folder's name.
+const MINUI_FOLDERS: [(&str, &str); 12] = [
+    ("gamegear", "Sega Game Gear (GG)"),
+    ("gb", "Game Boy (GB)"),
+    ("gba", "Game Boy Advance (GBA)"),
+    ("gbc", "Game Boy Color (GBC)"),
+    ("mastersystem", "Sega Master System (SMS)"),
+    ("megadrive", "Sega Genesis (MD)"),
+    ("nes", "Nintendo Entertainment System (FC)"),
+    ("pcengine", "TurboGrafx-16 (PCE)"),
+    ("psx", "Sony PlayStation (PS)"),
+    ("segacd", "Sega CD (SEGACD)"),
+    ("snes", "Super Nintendo Entertainment System (SFC)"),
+    ("virtualboy", "Virtual Boy (VB)"),
+];
+
+// Systems whose extensions differ from the built-in ones for a frontend.
+const PRESET_EXTENSIONS: [(Preset, &str, &[&str]); 6] = [
+    (
+        Preset::Batocera,
+        "dreamcast",
+        &["chd", "gdi", "cdi", "cue", "m3u"],
+    ),
+    (
+        Preset::EsDe,
+        "dreamcast",
+        &["chd", "gdi", "cdi", "cue", "m3u"],
+    ),
+    (Preset::Minui, "megadrive", &["md", "gen", "bin", "smd"]),
+    (Preset::Minui, "nes", &["nes", "fds"]),
+    (Preset::Onion, "megadrive", &["md", "gen", "bin", "smd"]),
+    (Preset::Onion, "nes", &["nes", "fds"]),
+];
+
+fn lookup(table: &[(&'static str, &'static str)], name: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(system, _)| *system == name)
+        .map(|(_, folder)| *folder)
+}
+
+impl Preset {
+    pub fn get_folder(&self, system: &KnownSystem) -> Option<&'static str> {
+        match self {
+            Self::Batocera | Self::EsDe => Some(system.name),
+            Self::Minui => lookup(&MINUI_FOLDERS, system.name),
+            Self::Onion => lookup(&ONION_FOLDERS, system.name),
+            Self::Retroarch => Some(system.full_name),
+        }
+    }
+
+    pub fn get_extensions(&self, system: &KnownSystem) -> Option<Vec<String>> {
+        PRESET_EXTENSIONS
+            .iter()
+            .find(|(preset, name, _)| preset == self && *name == system.name)
+            .map(|(_, _, extensions)| extensions.iter().map(|ext| ext.to_string()).collect())
+    }
+
+    // The settings a system gets from the preset when the destination doesn't set them itself.
+    pub fn to_system(&self, system: &KnownSystem) -> System {
+        System {
+            destination: self.get_folder(system).map(|folder| folder.to_string()),
+            extensions: self.get_extensions(system),
+            ..Default::default()
+        }
+    }
+}
+
+fn get_stem(path: &Path) -> Result<&str, Error> {
+    path.file_stem()
+        .ok_or_else(|| Error::from(format!("Failed to get filename for {}", path.display())))?
+        .to_str()
+        .ok_or_else(|| {
+            Error::from(format!(
+                "Failed to convert file name {} to UTF-8",
+                path.display()
+            ))
+        })
+}
+
+fn path_to_str(path: &Path) -> Result<&str, Error> {
+    path.to_str().ok_or_else(|| {
+        Error::from(format!(
+            "Failed to convert file path {} to UTF-8",
+            path.display()
+        ))
+    })
+}
+
+// Update a RetroArch playlist so that it has an item for each game. Items that are already in the
+// playlist are kept as they are, along with anything else that was changed in RetroArch, and items
+// for games that no longer exist are removed.
+pub fn update_lpl(
+    existing: Option<&str>,
+    games: &[PathBuf],
+    db_name: &str,
+    core: Option<&Core>,
+) -> Result<String, Error> {
+    let mut playlist: Value = match existing {
+        Some(contents) => serde_json::from_str(contents)
+            .map_err(|e| Error::Parse(format!("Failed to parse RetroArch playlist: {}", e)))?,
+        None => json!({
+            "version": "1.5",
+            "default_core_path": "",
+            "default_core_name": "",
+            "label_display_mode": 0,
+            "right_thumbnail_mode": 0,
+            "left_thumbnail_mode": 0,
+            "sort_mode": 0,
+            "items": [],
+        }),
+    };
+    let object = playlist
+        .as_object_mut()
+        .ok_or_else(|| Error::from("RetroArch playlist isn't a JSON object"))?;
+
+    if let Some(core) = core {
+        object.insert("default_core_path".to_string(), json!(core.path));
+        object.insert("default_core_name".to_string(), json!(core.name));
+    }
+
+    let mut paths = Vec::new();
+    for game in games {
+        paths.push(path_to_str(game)?);
+    }
+
+    let items = match object.remove("items") {
+        Some(Value::Array(items)) => items,
+        _ => vec![],
+    };
+    let mut seen = HashSet::new();
+    let mut items: Vec<Value> = items
+        .into_iter()
+        .filter(|item| {
+            item.get("path")
+                .and_then(Value::as_str)
+                .is_some_and(|path| paths.contains(&path) && seen.insert(path.to_string()))
+        })
+        .collect();
+    for (game, path) in games.iter().zip(&paths) {
+        if seen.contains(*path) {
+            continue;
+        }
+        items.push(json!({
+            "path": path,
+            "label": get_stem(game)?,
+            "core_path": "DETECT",
+            "core_name": "DETECT",
+            "crc32": "DETECT",
+            "db_name": db_name,
+        }));
+    }
+    object.insert("items".to_string(), Value::Array(items));
+
+    let mut contents = serde_json::to_string_pretty(&playlist)
+        .map_err(|e| format!("Failed to serialize RetroArch playlist: {}", e))?;
+    contents.push('\n');
+    Ok(contents)
+}
+
+// EmulationStation paths are relative to the gamelist's directory, e.g., `./Game.chd`.
+fn normalize_gamelist_path(path: &str) -> &str {
+    path.trim_start_matches("./")
+}
+
+fn text_element(name: &str, text: &str) -> XMLNode {
+    let mut element = Element::new(name);
+    element.children.push(XMLNode::Text(text.to_string()));
+    XMLNode::Element(element)
+}
+
+// Update an EmulationStation gamelist so that it has an entry for each game. Entries that are
+// already in the gamelist are kept as they are (e.g., favorites, play counts, and scraped media),
+// and entries for games that no longer exist are removed.
+pub fn update_gamelist(
+    existing: Option<&str>,
+    directory: &Path,
+    games: &[PathBuf],
+) -> Result<String, Error> {
+    let mut gamelist = match existing {
+        Some(contents) => Element::parse(contents.as_bytes())
+            .map_err(|e| Error::Parse(format!("Failed to parse gamelist: {}", e)))?,
+        None => Element::new("gameList"),
+    };
+
+    let mut entries = Vec::new();
+    for game in games {
+        let relative = game.strip_prefix(directory).map_err(|_| {
+            format!(
+                "{} isn't below the gamelist's directory {}",
+                game.display(),
+                directory.display()
+            )
+        })?;
+        entries.push((
+            path_to_str(relative)?.to_string(),
+            get_display_name(get_stem(game)?),
+        ));
+    }
+    let wanted: HashSet<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+
+    let mut seen = HashSet::new();
+    gamelist.children.retain(|node| match node {
+        XMLNode::Element(element) if element.name == "game" => element
+            .get_child("path")
+            .and_then(|path| path.get_text())
+            .is_some_and(|path| {
+                let path = normalize_gamelist_path(&path).to_string();
+                wanted.contains(path.as_str()) && seen.insert(path)
+            }),
+        _ => true,
+    });
+    for (path, name) in &entries {
+        if seen.contains(path) {
+            continue;
+        }
+        let mut game = Element::new("game");
+        game.children
+            .push(text_element("path", &format!("./{path}")));
+        game.children.push(text_element("name", name));
+        gamelist.children.push(XMLNode::Element(game));
+    }
+
+    let mut output = Vec::new();
+    gamelist
+        .write_with_config(&mut output, EmitterConfig::new().perform_indent(true))
+        .map_err(|e| format!("Failed to serialize gamelist: {}", e))?;
+    let mut contents =
+        String::from_utf8(output).map_err(|e| format!("Failed to decode gamelist: {}", e))?;
+    contents.push('\n');
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::systems::find_system;
+    use super::*;
+
+    #[test]
+    fn preset_to_system() {
+        let psx = find_system("psx").unwrap();
+        assert_eq!(
+            Preset::Onion.to_system(psx).get_destinations("psx"),
+            vec!["PS"]
+        );
+        assert_eq!(
+            Preset::Minui.to_system(psx).get_destinations("psx"),
+            vec!["Sony PlayStation (PS)"]
+        );
+        assert_eq!(
+            Preset::Retroarch.to_system(psx).get_destinations("psx"),
+            vec!["Sony - PlayStation"]
+        );
+
+        let nes = find_system("nes").unwrap();
+        let system = Preset::Onion.to_system(nes);
+        assert_eq!(system.get_destinations("nes"), vec!["FC"]);
+        assert_eq!(system.get_extensions("nes"), vec!["nes", "fds"]);
+
+        // Systems that a frontend doesn't have a name for are left alone.
+        let gc = find_system("gc").unwrap();
+        assert_eq!(Preset::Minui.to_system(gc).destination, None);
+    }
+
+    #[test]
+    fn update_lpl_creates_playlist() {
+        let contents = update_lpl(
+            None,
+            &[PathBuf::from("/roms/psx/Game (USA).m3u")],
+            "Sony - PlayStation.lpl",
+            Some(&Core {
+                path: "/cores/pcsx.so".to_string(),
+                name: "PCSX".to_string(),
+            }),
+        )
+        .unwrap();
+        let playlist: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(playlist["default_core_path"], "/cores/pcsx.so");
+        assert_eq!(playlist["default_core_name"], "PCSX");
+        assert_eq!(
+            playlist["items"],
+            json!([{
+                "path": "/roms/psx/Game (USA).m3u",
+                "label": "Game (USA)",
+                "core_path": "DETECT",
+                "core_name": "DETECT",
+                "crc32": "DETECT",
+                "db_name": "Sony - PlayStation.lpl",
+            }])
+        );
+    }
+
+    #[test]
+    fn update_lpl_keeps_existing_items() {
+        let existing = json!({
+            "version": "1.5",
+            "default_core_path": "/cores/user.so",
+            "items": [
+                {"path": "/roms/Kept.chd", "label": "Renamed", "core_path": "/cores/other.so"},
+                {"path": "/roms/Removed.chd", "label": "Removed"},
+            ],
+        })
+        .to_string();
+
+        let contents = update_lpl(
+            Some(&existing),
+            &[
+                PathBuf::from("/roms/Kept.chd"),
+                PathBuf::from("/roms/New.chd"),
+            ],
+            "psx.lpl",
+            None,
+        )
+        .unwrap();
+        let playlist: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(playlist["default_core_path"], "/cores/user.so");
+        let items = playlist["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0],
+            json!({"path": "/roms/Kept.chd", "label": "Renamed", "core_path": "/cores/other.so"})
+        );
+        assert_eq!(items[1]["path"], "/roms/New.chd");
+    }
+
+    #[test]
+    fn update_gamelist_keeps_user_fields() {
+        let existing = r#"<?xml version="1.0"?>
+<gameList>
+  <game>
+    <path>./Kept (USA).chd</path>
+    <name>My Name</name>
+    <favorite>true</favorite>
+  </game>
+  <game>
+    <path>./Removed (USA).chd</path>
+    <name>Removed</name>
+  </game>
+  <folder>
+    <path>./sub</path>
+  </folder>
+</gameList>
+"#;
+        let contents = update_gamelist(
+            Some(existing),
+            Path::new("/roms/psx"),
+            &[
+                PathBuf::from("/roms/psx/Kept (USA).chd"),
+                PathBuf::from("/roms/psx/sub/Game, The (USA).m3u"),
+            ],
+        )
+        .unwrap();
+        let gamelist = Element::parse(contents.as_bytes()).unwrap();
+        let games: Vec<&Element> = gamelist
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .collect();
+        assert_eq!(games.len(), 3);
+        assert_eq!(
+            games[0].get_child("favorite").unwrap().get_text().unwrap(),
+            "true"
+        );
+        assert_eq!(games[1].name, "folder");
+        assert_eq!(
+            games[2].get_child("path").unwrap().get_text().unwrap(),
+            "./sub/Game, The (USA).m3u"
+        );
+        assert_eq!(
+            games[2].get_child("name").unwrap().get_text().unwrap(),
+            "The Game"
+        );
+    }
+}
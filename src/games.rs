@@ -1,37 +1,47 @@
-use std::env::set_current_dir;
+use std::collections::{BTreeSet, HashSet};
 use std::fs::{canonicalize, create_dir_all, remove_file, symlink_metadata};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use log::{debug, error, info, warn};
 
-use super::config::load_link_destination_config;
-use super::utils::{capture_output, find_files_with_extension};
+use super::config::LinkDestinationConfig;
+use super::disc::{get_disc_directory, get_referenced_files, is_descriptor};
+use super::error::Error;
+use super::utils::{capture_output, find_files, find_files_with_extension};
 
+// A symlink at `path` that points to `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub target: PathBuf,
+    pub path: PathBuf,
+}
+
+fn get_systems<'a>(
+    config: &'a LinkDestinationConfig,
+    systems: &'a [String],
+    all_systems: bool,
+) -> Vec<&'a String> {
+    if all_systems {
+        let mut systems: Vec<&String> = config.systems.keys().collect();
+        systems.sort();
+        systems
+    } else {
+        systems.iter().collect()
+    }
+}
+
+// Find the broken links below destination for each system. Nothing is removed; pass the links to
+// `remove_links` to do that.
 pub fn clean(
     destination: &Path,
+    config: &LinkDestinationConfig,
     systems: &[String],
     all_systems: bool,
-    dry_run: bool,
-) -> Result<(), String> {
-    set_current_dir(destination).map_err(|e| {
-        format!(
-            "Failed to change directory to {}: {}",
-            destination.display(),
-            e
-        )
-    })?;
-
-    let config = load_link_destination_config(None)?;
-
-    let configured_systems = config.get_system_names();
-    let systems_to_clean = if all_systems {
-        &configured_systems
-    } else {
-        systems
-    };
+) -> Result<Vec<PathBuf>, Error> {
+    let mut broken_links = Vec::new();
 
-    for system in systems_to_clean {
+    for system in get_systems(config, systems, all_systems) {
         let Some(system_config) = config.systems.get(system) else {
             info!("{system} not found in config. Skipping.");
             continue;
@@ -45,134 +55,520 @@ pub fn clean(
             if let Some(extra_path) = &system_config.extra_path {
                 path = path.join(extra_path);
             }
-            let _ = set_current_dir(&path).is_ok();
             debug!("Checking for broken {extensions:?} links in {path:?}.");
 
             let extensions_slice: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
             let files_to_clean = find_files_with_extension(&path, &extensions_slice)?;
 
-            for file in &files_to_clean {
-                let metadata = symlink_metadata(file)
-                    .map_err(|e| format!("Failed to get metadata for {}: {}", file.display(), e))?;
-                if metadata.is_symlink() {
-                    if canonicalize(file).is_err() {
-                        if dry_run {
-                            error!("Broken symlink found at {file:?}. Skipping.");
-                        } else {
-                            if let Err(e) = remove_file(file) {
-                                error!("Failed to remove broken symlink {}: {}", file.display(), e);
-                            } else {
-                                error!("{file:?} unlinked");
-                            };
-                        }
-                    }
+            for file in files_to_clean {
+                let metadata = symlink_metadata(&file)
+                    .map_err(|e| Error::io("Failed to get metadata for", &file, e))?;
+                if metadata.is_symlink() && canonicalize(&file).is_err() {
+                    broken_links.push(file);
                 }
             }
         }
     }
 
+    Ok(broken_links)
+}
+
+// Remove links found by `clean`. A link that can't be removed doesn't stop the rest from being
+// removed.
+pub fn remove_links(links: &[PathBuf]) -> Result<(), Error> {
+    let mut failures = 0;
+    for link in links {
+        if let Err(e) = remove_file(link) {
+            error!("Failed to remove broken symlink {}: {}", link.display(), e);
+            failures += 1;
+        } else {
+            error!("{link:?} unlinked");
+        }
+    }
+
+    if failures > 0 {
+        return Err(Error::Partial(format!(
+            "Failed to remove {} broken symlink(s)",
+            failures
+        )));
+    }
     Ok(())
 }
 
+// Add the tracks that each descriptor references right after it, so that linking a cue sheet also
+// links its bins. Each file is paired with the path it's linked to, relative to the destination.
+fn with_referenced_tracks(files: Vec<PathBuf>) -> Vec<(PathBuf, PathBuf)> {
+    let mut seen = HashSet::new();
+    let mut with_tracks = Vec::new();
+    for file in files {
+        let directory = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let tracks = if is_descriptor(&file) {
+            get_referenced_files(&file).unwrap_or_else(|e| {
+                warn!("{e}");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        for file in std::iter::once(file).chain(tracks.into_iter().filter(|track| track.is_file()))
+        {
+            if !seen.insert(file.clone()) {
+                continue;
+            }
+            let name = match file.strip_prefix(&directory) {
+                Ok(name) => name.to_path_buf(),
+                Err(_) => PathBuf::from(file.file_name().unwrap_or_default()),
+            };
+            with_tracks.push((file, name));
+        }
+    }
+    with_tracks
+}
+
+// Every file in a disc directory, paired with its path relative to the directory's parent (e.g.,
+// `.discs/Game (Disc 1).chd`). Disc directories are recreated in destinations as real directories
+// so that the disc directories of several sources can be linked into the same one.
+fn get_disc_directory_files(directory: &Path) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let parent = directory.parent().unwrap_or(Path::new(""));
+    let mut files = find_files(directory)?;
+    files.sort();
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let name = file.strip_prefix(parent).unwrap_or(&file).to_path_buf();
+            (file, name)
+        })
+        .collect())
+}
+
+// Find the links that need to be created below destination for the games in sources, which are in
+// order of preference. When a game is in more than one source (or is backed up by more than one of a
+// system's dumpers), the first one is linked. Games that are already linked are left out. Nothing is
+// linked; pass the links to `create_links` to do that.
 pub fn link(
-    source: &Path,
+    sources: &[PathBuf],
     destination: &Path,
+    config: &LinkDestinationConfig,
     systems: &[String],
     all_systems: bool,
-) -> Result<(), String> {
-    set_current_dir(destination).map_err(|e| {
-        format!(
-            "Failed to change directory to {}: {}",
-            destination.display(),
-            e
-        )
-    })?;
-
-    let config = load_link_destination_config(None)?;
+) -> Result<Vec<Link>, Error> {
+    let mut links = Vec::new();
 
-    let configured_systems = config.get_system_names();
-    let systems_to_link = if all_systems {
-        &configured_systems
-    } else {
-        systems
-    };
-
-    for system in systems_to_link {
+    for system in get_systems(config, systems, all_systems) {
         let Some(system_config) = config.systems.get(system) else {
             info!("{system} not found in config. Skipping.");
             continue;
         };
 
-        let system_source = Path::new(&source).join(&system_config.dumper).join(&system);
-        if !system_source.is_dir() {
-            info!("{} does not exist. Skipping.", system_source.display());
+        let system_sources: Vec<PathBuf> = system_config
+            .get_sources(sources, system)
+            .into_iter()
+            .filter(|system_source| {
+                let exists = system_source.is_dir();
+                if !exists {
+                    debug!("{} does not exist.", system_source.display());
+                }
+                exists
+            })
+            .collect();
+        if system_sources.is_empty() {
+            info!("{system} not found in any source. Skipping.");
             continue;
         }
 
         let extensions = system_config.get_extensions(system);
-
         let extensions_slice: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
-        let files_to_link = find_files_with_extension(&system_source, &extensions_slice)?;
-
         let destinations = system_config.get_destinations(system);
-        for link_destination in destinations {
-            let (current_system_source, path) = if let Some(extra_path) = &system_config.extra_path
-            {
-                (
-                    system_source.join(extra_path),
-                    destination.join(&link_destination).join(extra_path),
-                )
-            } else {
-                // Clone is necessary here since we need an owned PathBuf and
-                // system_source must be reused for the next iteration
-                (system_source.clone(), destination.join(&link_destination))
-            };
-            create_dir_all(&path)
-                .map_err(|e| format!("Failed to create directory {}: {}", path.display(), e))?;
-            let _ = set_current_dir(&path).is_ok();
-            debug!("Linking {extensions:?} from {current_system_source:?} to {path:?}.");
-
-            if !current_system_source.is_dir() {
-                info!(
-                    "{} does not exist. Skipping.",
-                    current_system_source.display()
-                );
-                continue;
+
+        // Every path that a link is planned for (or that's already linked), so that games found
+        // again in a later source are skipped.
+        let mut planned = HashSet::new();
+
+        for system_source in &system_sources {
+            // Discs that live in a disc directory are linked with everything else in the directory
+            // so that each multidisc game only shows up once, through its playlist.
+            let mut disc_directories = BTreeSet::new();
+            let mut loose_files = Vec::new();
+            for file in find_files_with_extension(system_source, &extensions_slice)? {
+                match get_disc_directory(system_source, &file) {
+                    Some(directory) => {
+                        disc_directories.insert(directory.to_path_buf());
+                    }
+                    None => loose_files.push(file),
+                }
+            }
+            let mut files_to_link = with_referenced_tracks(loose_files);
+            for directory in &disc_directories {
+                files_to_link.extend(get_disc_directory_files(directory)?);
             }
 
-            for file in &files_to_link {
-                let destination_file_name = file
-                    .file_name()
-                    .ok_or_else(|| format!("Failed to get filename for {}", file.display()))?;
-                let destination_path = path.join(destination_file_name);
-                if destination_path.exists() {
-                    let metadata = symlink_metadata(&destination_path).map_err(|e| {
-                        format!(
-                            "Failed to get metadata for {}: {}",
-                            destination_path.display(),
-                            e
+            for link_destination in &destinations {
+                let (current_system_source, path) =
+                    if let Some(extra_path) = &system_config.extra_path {
+                        (
+                            system_source.join(extra_path),
+                            destination.join(link_destination).join(extra_path),
                         )
-                    })?;
-                    if metadata.is_symlink() {
-                        if let Ok(canonical) = canonicalize(&destination_path) {
-                            if canonical == *file {
-                                warn!("{destination_file_name:?} already linked. Skipping.");
-                                continue;
+                    } else {
+                        (system_source.clone(), destination.join(link_destination))
+                    };
+                debug!("Linking {extensions:?} from {current_system_source:?} to {path:?}.");
+
+                if !current_system_source.is_dir() {
+                    info!(
+                        "{} does not exist. Skipping.",
+                        current_system_source.display()
+                    );
+                    continue;
+                }
+
+                for (file, destination_file_name) in &files_to_link {
+                    let destination_path = path.join(destination_file_name);
+                    if !planned.insert(destination_path.clone()) {
+                        warn!("{file:?} was found in an earlier source. Skipping.");
+                        continue;
+                    }
+                    // A disc directory that was linked as a whole leads back into a source.
+                    if let Some(directory) = destination_path.parent() {
+                        if directory != path && directory.is_symlink() {
+                            warn!(
+                                "{} is a symlink, so {file:?} can't be linked in it. Skipping.",
+                                directory.display()
+                            );
+                            continue;
+                        }
+                    }
+                    if destination_path.exists() {
+                        let metadata = symlink_metadata(&destination_path).map_err(|e| {
+                            Error::io("Failed to get metadata for", &destination_path, e)
+                        })?;
+                        if metadata.is_symlink() {
+                            if let Ok(canonical) = canonicalize(&destination_path) {
+                                if canonical == *file {
+                                    warn!("{destination_file_name:?} already linked. Skipping.");
+                                    continue;
+                                }
                             }
                         }
                     }
+                    links.push(Link {
+                        target: file.clone(),
+                        path: destination_path,
+                    });
                 }
-                let file_str = file.to_str().ok_or_else(|| {
-                    format!("Failed to convert file path {} to UTF-8", file.display())
-                })?;
-                let output = capture_output(
-                    &mut Command::new("ln").args(["-s", "-F", "-f", "-v", file_str]),
-                    "Failed to link",
-                )?;
-                error!("{output}");
             }
         }
     }
 
+    Ok(links)
+}
+
+fn create_link(link: &Link) -> Result<String, Error> {
+    let directory = link.path.parent().unwrap_or(Path::new(""));
+    create_dir_all(directory).map_err(|e| Error::io("Failed to create directory", directory, e))?;
+    let target = link.target.to_str().ok_or_else(|| {
+        format!(
+            "Failed to convert file path {} to UTF-8",
+            link.target.display()
+        )
+    })?;
+    // Don't follow an existing link to a directory, which would put the new link inside of it.
+    let flags = if link.target.is_dir() {
+        ["-s", "-F", "-f", "-n", "-v"].as_slice()
+    } else {
+        ["-s", "-F", "-f", "-v"].as_slice()
+    };
+    capture_output(
+        Command::new("ln")
+            .args(flags)
+            .arg(target)
+            .current_dir(directory),
+        "Failed to link",
+    )
+}
+
+// Create links found by `link`, replacing anything that's already at their paths. A link that
+// can't be created doesn't stop the rest from being created.
+pub fn create_links(links: &[Link]) -> Result<(), Error> {
+    let mut failures = 0;
+    for link in links {
+        match create_link(link) {
+            Ok(output) => error!("{output}"),
+            Err(e) => {
+                error!("Failed to link {}: {}", link.path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(Error::Partial(format!(
+            "Failed to create {} link(s)",
+            failures
+        )));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, write};
+    use std::os::unix::fs::symlink;
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::super::config::System;
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Context {
+            Context {
+                root: TempDir::new("games").unwrap(),
+            }
+        }
+    }
+
+    fn get_config() -> LinkDestinationConfig {
+        LinkDestinationConfig {
+            preset: None,
+            systems: HashMap::from([(
+                "gba".to_string(),
+                System {
+                    destination: Some("GBA".to_string()),
+                    dumper: vec!["no-intro".to_string()],
+                    ..Default::default()
+                },
+            )]),
+        }
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn link_plans_missing_links(ctx: &mut Context) {
+        let source = ctx.root.path().join("source");
+        let destination = ctx.root.path().join("destination");
+        let games = source.join("no-intro").join("gba");
+        create_dir_all(&games).unwrap();
+        write(games.join("Linked.gba"), "").unwrap();
+        write(games.join("New.gba"), "").unwrap();
+        write(games.join("Ignored.txt"), "").unwrap();
+        create_dir_all(destination.join("GBA")).unwrap();
+        symlink(
+            canonicalize(games.join("Linked.gba")).unwrap(),
+            destination.join("GBA").join("Linked.gba"),
+        )
+        .unwrap();
+
+        let source = canonicalize(source).unwrap();
+        let links = link(
+            std::slice::from_ref(&source),
+            &destination,
+            &get_config(),
+            &[],
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            links,
+            vec![Link {
+                target: source.join("no-intro").join("gba").join("New.gba"),
+                path: destination.join("GBA").join("New.gba"),
+            }]
+        );
+        assert!(!destination.join("GBA").join("New.gba").exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn link_prefers_earlier_sources_and_dumpers(ctx: &mut Context) {
+        let ssd = ctx.root.path().join("ssd");
+        let nas = ctx.root.path().join("nas");
+        let destination = ctx.root.path().join("destination");
+        for (source, dumper, game) in [
+            (&nas, "no-intro", "Both.gba"),
+            (&nas, "no-intro", "Nas.gba"),
+            (&nas, "mine", "Hack.gba"),
+            (&ssd, "mine", "Both.gba"),
+            (&ssd, "mine", "Hack.gba"),
+        ] {
+            let games = source.join(dumper).join("gba");
+            create_dir_all(&games).unwrap();
+            write(games.join(game), "").unwrap();
+        }
+
+        let mut config = get_config();
+        config.systems.get_mut("gba").unwrap().dumper =
+            vec!["no-intro".to_string(), "mine".to_string()];
+        let ssd = canonicalize(ssd).unwrap();
+        let nas = canonicalize(nas).unwrap();
+        let mut links = link(
+            &[ssd.clone(), nas.clone()],
+            &destination,
+            &config,
+            &[],
+            true,
+        )
+        .unwrap();
+        links.sort_by(|a, b| a.path.cmp(&b.path));
+        let gba = destination.join("GBA");
+        assert_eq!(
+            links,
+            vec![
+                Link {
+                    target: ssd.join("mine").join("gba").join("Both.gba"),
+                    path: gba.join("Both.gba"),
+                },
+                Link {
+                    target: ssd.join("mine").join("gba").join("Hack.gba"),
+                    path: gba.join("Hack.gba"),
+                },
+                Link {
+                    target: nas.join("no-intro").join("gba").join("Nas.gba"),
+                    path: gba.join("Nas.gba"),
+                },
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn clean_finds_broken_links(ctx: &mut Context) {
+        let destination = ctx.root.path().join("GBA");
+        create_dir_all(&destination).unwrap();
+        write(ctx.root.path().join("Game.gba"), "").unwrap();
+        symlink(
+            ctx.root.path().join("Game.gba"),
+            destination.join("Game.gba"),
+        )
+        .unwrap();
+        symlink(
+            ctx.root.path().join("Gone.gba"),
+            destination.join("Gone.gba"),
+        )
+        .unwrap();
+
+        let links = clean(ctx.root.path(), &get_config(), &["gba".to_string()], false).unwrap();
+        assert_eq!(links, vec![destination.join("Gone.gba")]);
+
+        remove_links(&links).unwrap();
+        assert!(symlink_metadata(destination.join("Gone.gba")).is_err());
+        assert!(destination.join("Game.gba").exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn create_links_keeps_going_after_a_failure(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.gba"), "").unwrap();
+        // A file where a directory needs to be.
+        write(root.join("GB"), "").unwrap();
+        let links = [
+            Link {
+                path: root.join("GB").join("Game.gba"),
+                target: root.join("Game.gba"),
+            },
+            Link {
+                path: root.join("GBA").join("Game.gba"),
+                target: root.join("Game.gba"),
+            },
+        ];
+
+        assert!(matches!(create_links(&links), Err(Error::Partial(_))));
+        assert!(root.join("GBA").join("Game.gba").is_symlink());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn link_plans_tracks_with_their_descriptor(ctx: &mut Context) {
+        let source = canonicalize(ctx.root.path()).unwrap().join("source");
+        let destination = ctx.root.path().join("destination");
+        let games = source.join("redump").join("psx");
+        create_dir_all(&games).unwrap();
+        write(
+            games.join("Game.cue"),
+            "FILE \"Game (Track 1).bin\" BINARY\nFILE \"Game (Track 2).bin\" BINARY\n",
+        )
+        .unwrap();
+        write(games.join("Game (Track 1).bin"), "").unwrap();
+        write(games.join("Game (Track 2).bin"), "").unwrap();
+        let config = LinkDestinationConfig {
+            preset: None,
+            systems: HashMap::from([(
+                "psx".to_string(),
+                System {
+                    dumper: vec!["redump".to_string()],
+                    ..Default::default()
+                },
+            )]),
+        };
+
+        let links = link(&[source], &destination, &config, &[], true).unwrap();
+        let paths: Vec<PathBuf> = links.into_iter().map(|link| link.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                destination.join("psx").join("Game.cue"),
+                destination.join("psx").join("Game (Track 1).bin"),
+                destination.join("psx").join("Game (Track 2).bin"),
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn link_plans_disc_directories_from_every_source(ctx: &mut Context) {
+        let root = canonicalize(ctx.root.path()).unwrap();
+        let ssd = root.join("ssd");
+        let nas = root.join("nas");
+        let destination = root.join("destination");
+        for (source, game) in [(&ssd, "First"), (&nas, "Second")] {
+            let games = source.join("redump").join("psx");
+            create_dir_all(games.join(".discs")).unwrap();
+            write(
+                games.join(format!("{game}.m3u")),
+                format!(".discs/{game} (Disc 1).chd\n.discs/{game} (Disc 2).chd\n"),
+            )
+            .unwrap();
+            for disc in 1..=2 {
+                write(
+                    games
+                        .join(".discs")
+                        .join(format!("{game} (Disc {disc}).chd")),
+                    "",
+                )
+                .unwrap();
+            }
+        }
+        let config = LinkDestinationConfig {
+            preset: None,
+            systems: HashMap::from([(
+                "psx".to_string(),
+                System {
+                    dumper: vec!["redump".to_string()],
+                    ..Default::default()
+                },
+            )]),
+        };
+
+        let links = link(&[ssd, nas], &destination, &config, &[], true).unwrap();
+        let paths: Vec<PathBuf> = links.into_iter().map(|link| link.path).collect();
+        let psx = destination.join("psx");
+        assert_eq!(
+            paths,
+            vec![
+                psx.join("First.m3u"),
+                psx.join(".discs").join("First (Disc 1).chd"),
+                psx.join(".discs").join("First (Disc 2).chd"),
+                psx.join("Second.m3u"),
+                psx.join(".discs").join("Second (Disc 1).chd"),
+                psx.join(".discs").join("Second (Disc 2).chd"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, error, info, warn};
+
+use super::config::{get_global_config_path, Config, LinkConfig, LinkDestinationConfig, System};
+use super::error::Error;
+use super::frontend::Preset;
+use super::systems::find_system;
+
+#[derive(Debug, clap::Args)]
+#[command(about = "Create config files for an existing library")]
+pub struct Args {
+    #[arg(help = "The directory with the backups, laid out as dumper/system")]
+    source: PathBuf,
+
+    #[arg(
+        short,
+        long = "destination",
+        help = "Where to link games to (can be repeated)"
+    )]
+    destinations: Vec<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "The frontend the destinations are for"
+    )]
+    preset: Option<Preset>,
+
+    #[arg(short, long, help = "Don't ask any questions")]
+    yes: bool,
+
+    #[arg(short, long, help = "Overwrite existing config files")]
+    force: bool,
+
+    #[arg(long, help = "Show the config files instead of writing them")]
+    dry_run: bool,
+}
+
+impl Args {
+    pub fn dispatch(self) -> Result<(), Error> {
+        init(self, &get_global_config_path()?)
+    }
+}
+
+// A system found in the source along with the dumpers it's backed up by, e.g., `psx` in `redump/psx`.
+#[derive(Debug, PartialEq)]
+struct FoundSystem {
+    dumpers: Vec<String>,
+    system: String,
+}
+
+fn get_directory_names(path: &Path) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path).map_err(|e| Error::io("Failed to read directory", path, e))? {
+        let entry = entry.map_err(|e| Error::io("Failed to read directory entry in", path, e))?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+        match entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') => names.push(name),
+            Ok(_) => {}
+            Err(name) => warn!("{name:?} isn't valid UTF-8. Skipping."),
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Find the systems in the source, sorted by name. A system's usual dumper comes first, followed by
+// any others alphabetically.
+fn find_systems(source: &Path) -> Result<Vec<FoundSystem>, Error> {
+    let mut systems: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for dumper in get_directory_names(source)? {
+        for system in get_directory_names(&source.join(&dumper))? {
+            systems.entry(system).or_default().push(dumper.clone());
+        }
+    }
+    Ok(systems
+        .into_iter()
+        .map(|(system, mut dumpers)| {
+            if let Some(known) = find_system(&system) {
+                dumpers.sort_by_key(|dumper| dumper != known.dumper);
+            }
+            FoundSystem { dumpers, system }
+        })
+        .collect())
+}
+
+// Known systems that are only backed up by their usual dumper don't need to be in the catalog.
+// Anything else gets a catalog entry with its dumpers.
+fn build_catalog(systems: &[FoundSystem]) -> HashMap<String, System> {
+    let mut catalog = HashMap::new();
+    for found in systems {
+        let known = find_system(&found.system);
+        if known.is_none() {
+            warn!(
+                "{} isn't a known system. Set its extensions in the config.",
+                found.system
+            );
+        }
+        if known.is_some_and(|known| found.dumpers == [known.dumper]) {
+            continue;
+        }
+        catalog.insert(
+            found.system.clone(),
+            System {
+                dumper: found.dumpers.clone(),
+                ..Default::default()
+            },
+        );
+    }
+    catalog
+}
+
+fn to_toml<T: serde::Serialize>(config: &T) -> Result<String, Error> {
+    // Going through a table sorts the keys.
+    let table =
+        toml::Table::try_from(config).map_err(|e| format!("Failed to serialize config: {}", e))?;
+    toml::to_string_pretty(&table)
+        .map_err(|e| Error::from(format!("Failed to serialize config: {}", e)))
+}
+
+fn path_to_string(path: &Path) -> Result<String, Error> {
+    path.to_str().map(|path| path.to_string()).ok_or_else(|| {
+        Error::from(format!(
+            "Failed to convert path {} to UTF-8",
+            path.display()
+        ))
+    })
+}
+
+// Plan the config files to write, the global config first and then one for each destination.
+fn plan_config_files(
+    source: &Path,
+    destinations: &[PathBuf],
+    preset: Option<Preset>,
+    systems: &[FoundSystem],
+    global_config_path: &Path,
+) -> Result<Vec<(PathBuf, String)>, Error> {
+    let global = Config {
+        link: LinkConfig {
+            source: path_to_string(source)?,
+            destinations: destinations
+                .iter()
+                .map(|destination| path_to_string(destination))
+                .collect::<Result<_, _>>()?,
+            ..Default::default()
+        },
+        systems: build_catalog(systems),
+    };
+    let destination_config = LinkDestinationConfig {
+        preset,
+        systems: systems
+            .iter()
+            .map(|found| (found.system.clone(), System::default()))
+            .collect(),
+    };
+    let destination_contents = to_toml(&destination_config)?;
+
+    let mut files = vec![(global_config_path.to_path_buf(), to_toml(&global)?)];
+    for destination in destinations {
+        files.push((destination.join("retro.toml"), destination_contents.clone()));
+    }
+    Ok(files)
+}
+
+fn prompt(question: &str) -> Result<String, Error> {
+    print!("{question}");
+    stdout()
+        .flush()
+        .map_err(|e| format!("Failed to write to stdout: {}", e))?;
+    let mut answer = String::new();
+    stdin()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Failed to read from stdin: {}", e))?;
+    Ok(answer.trim().to_string())
+}
+
+fn prompt_for_destinations() -> Result<Vec<PathBuf>, Error> {
+    let mut destinations = Vec::new();
+    loop {
+        let answer = prompt("Destination to link games to (leave empty when done): ")?;
+        if answer.is_empty() {
+            break Ok(destinations);
+        }
+        destinations.push(PathBuf::from(answer));
+    }
+}
+
+fn prompt_for_preset() -> Result<Option<Preset>, Error> {
+    loop {
+        let answer = prompt(
+            "Frontend preset (batocera, es-de, minui, onion, retroarch, or empty for none): ",
+        )?;
+        if answer.is_empty() {
+            break Ok(None);
+        }
+        match clap::ValueEnum::from_str(&answer, true) {
+            Ok(preset) => break Ok(Some(preset)),
+            Err(_) => error!("Unknown preset '{answer}'"),
+        }
+    }
+}
+
+fn init(args: Args, global_config_path: &Path) -> Result<(), Error> {
+    let source = std::path::absolute(&args.source)
+        .map_err(|e| Error::io("Failed to find", &args.source, e))?;
+    if !source.is_dir() {
+        return Err(format!("{} isn't a directory", source.display()).into());
+    }
+    debug!("Looking for systems in {source:?}");
+
+    let systems = find_systems(&source)?;
+    if systems.is_empty() {
+        info!("No systems found in {}", source.display());
+    }
+    for found in &systems {
+        debug!("Found {} from {}", found.system, found.dumpers.join(", "));
+    }
+
+    let interactive = !args.yes && !args.dry_run && stdin().is_terminal();
+    let mut destinations = args.destinations;
+    let mut preset = args.preset;
+    if interactive {
+        error!("Found {} system(s) in {}", systems.len(), source.display());
+        if destinations.is_empty() {
+            destinations = prompt_for_destinations()?;
+        }
+        if preset.is_none() && !destinations.is_empty() {
+            preset = prompt_for_preset()?;
+        }
+    }
+    let destinations = destinations
+        .iter()
+        .map(|destination| {
+            std::path::absolute(destination)
+                .map_err(|e| Error::io("Failed to find", destination, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let files = plan_config_files(&source, &destinations, preset, &systems, global_config_path)?;
+    let files: Vec<(PathBuf, String)> = files
+        .into_iter()
+        .filter(|(path, _)| {
+            if path.exists() && !args.force {
+                warn!("{} exists. Skipping.", path.display());
+                return false;
+            }
+            true
+        })
+        .collect();
+
+    if args.dry_run {
+        for (path, contents) in &files {
+            error!("Would write {path:?}\n{}", contents.trim_end());
+        }
+        return Ok(());
+    }
+
+    if interactive && !files.is_empty() {
+        let answer = prompt(&format!("Write {} config file(s)? [y/N] ", files.len()))?;
+        if !answer.eq_ignore_ascii_case("y") {
+            info!("Nothing written");
+            return Ok(());
+        }
+    }
+
+    for (path, contents) in files {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)
+                .map_err(|e| Error::io("Failed to create directory", directory, e))?;
+        }
+        error!("Writing {path:?}");
+        fs::write(&path, contents).map_err(|e| Error::io("Failed to write to", &path, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::super::config::load_config_file;
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Context {
+            let root = TempDir::new("init").unwrap();
+            for directory in [
+                "no-intro/gba",
+                "redump/psx",
+                "mine/gba",
+                "mine/psx2",
+                "mine/.hidden",
+            ] {
+                create_dir_all(root.path().join("source").join(directory)).unwrap();
+            }
+            Context { root }
+        }
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_systems_puts_usual_dumper_first(ctx: &mut Context) {
+        let systems = find_systems(&ctx.root.path().join("source")).unwrap();
+        let systems: Vec<(&str, Vec<&str>)> = systems
+            .iter()
+            .map(|found| {
+                let dumpers = found.dumpers.iter().map(|dumper| dumper.as_str()).collect();
+                (found.system.as_str(), dumpers)
+            })
+            .collect();
+        assert_eq!(
+            systems,
+            vec![
+                ("gba", vec!["no-intro", "mine"]),
+                ("psx", vec!["redump"]),
+                ("psx2", vec!["mine"]),
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn init_writes_config_files(ctx: &mut Context) {
+        let source = ctx.root.path().join("source");
+        let destination = ctx.root.path().join("sdcard");
+        let global = ctx.root.path().join("config").join("retro.toml");
+        let args = Args {
+            source: source.clone(),
+            destinations: vec![destination.clone()],
+            preset: Some(Preset::Onion),
+            yes: true,
+            force: false,
+            dry_run: false,
+        };
+        init(args, &global).unwrap();
+
+        let config: Config = load_config_file(&global).unwrap();
+        assert_eq!(config.link.source, source.to_str().unwrap());
+        assert_eq!(
+            config.link.destinations,
+            vec![destination.to_str().unwrap()]
+        );
+        let mut catalog: Vec<(&String, &Vec<String>)> = config
+            .systems
+            .iter()
+            .map(|(name, system)| (name, &system.dumper))
+            .collect();
+        catalog.sort();
+        assert_eq!(
+            catalog,
+            vec![
+                (
+                    &"gba".to_string(),
+                    &vec!["no-intro".to_string(), "mine".to_string()]
+                ),
+                (&"psx2".to_string(), &vec!["mine".to_string()]),
+            ]
+        );
+
+        let mut config: LinkDestinationConfig =
+            load_config_file(&destination.join("retro.toml")).unwrap();
+        assert_eq!(config.preset, Some(Preset::Onion));
+        config.inherit(&HashMap::new());
+        assert_eq!(config.systems["psx"].get_destinations("psx"), vec!["PS"]);
+        assert_eq!(config.systems["psx"].dumper, vec!["redump"]);
+        assert_eq!(config.systems.len(), 3);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn init_skips_existing_config_files(ctx: &mut Context) {
+        let global = ctx.root.path().join("retro.toml");
+        fs::write(&global, "").unwrap();
+        let args = Args {
+            source: ctx.root.path().join("source"),
+            destinations: vec![],
+            preset: None,
+            yes: true,
+            force: false,
+            dry_run: false,
+        };
+        init(args, &global).unwrap();
+        assert_eq!(fs::read_to_string(&global).unwrap(), "");
+    }
+}
@@ -0,0 +1,27 @@
+//! Retro game catalog management.
+//!
+//! The `retro` command is built on top of this library, which can also be used on its own, e.g., to
+//! link games, parse DAT files, or find multidisc games that need playlists.
+
+pub mod config;
+pub mod cue;
+pub mod dat;
+pub mod disc;
+pub mod error;
+pub mod frontend;
+pub mod games;
+pub mod name;
+pub mod playlist;
+pub mod rename;
+pub mod systems;
+
+#[doc(hidden)]
+pub mod cli;
+mod compress;
+mod init;
+mod link;
+mod merge_bin;
+mod plan;
+mod utils;
+
+pub use error::Error;
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use log::{debug, error};
 
-use super::config::load_global_config;
+use super::config::{load_global_config, load_link_destination_config, Destination, System};
+use super::error::Error;
 use super::games;
 
 #[derive(Debug, clap::Args)]
@@ -26,6 +30,13 @@ enum Commands {
 
         #[arg(long, help = "Don't remove the broken links")]
         dry_run: bool,
+
+        #[arg(
+            short,
+            long = "dest",
+            help = "Destination to clean up (can be repeated)"
+        )]
+        destinations: Vec<String>,
     },
 
     #[command(about = "Create links for backed up games")]
@@ -33,8 +44,21 @@ enum Commands {
 }
 
 #[derive(Debug, clap::Args)]
-#[group(required = true, multiple = false)]
 struct LinkArgs {
+    #[command(flatten)]
+    systems: SystemArgs,
+
+    #[arg(
+        short,
+        long = "dest",
+        help = "Destination to synchronize (can be repeated)"
+    )]
+    destinations: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+#[group(required = true, multiple = false)]
+struct SystemArgs {
     #[arg(help = "System to synchronize")]
     system: Vec<String>,
 
@@ -43,41 +67,115 @@ struct LinkArgs {
 }
 
 impl Args {
-    pub fn dispatch(self) -> Result<(), String> {
+    pub fn dispatch(self) -> Result<(), Error> {
         let cmd = self.command.unwrap_or(Commands::Link(self.link));
         match cmd {
             Commands::Clean {
                 system,
                 all,
                 dry_run,
-            } => clean_links(system, all, dry_run),
+                destinations,
+            } => clean_links(system, all, dry_run, &destinations),
 
-            Commands::Link(args) => link(args.system, args.all),
+            Commands::Link(args) => link(args.systems.system, args.systems.all, &args.destinations),
         }
     }
 }
 
-fn clean_links(systems: Vec<String>, all_systems: bool, dry_run: bool) -> Result<(), String> {
-    let config = load_global_config()?.link;
-
-    for destination in config.expand_destinations() {
-        if let Err(e) = games::clean(&destination, &systems, all_systems, dry_run) {
-            error!("{e:#?}");
+fn clean_links(
+    systems: Vec<String>,
+    all_systems: bool,
+    dry_run: bool,
+    destinations: &[String],
+) -> Result<(), Error> {
+    let config = load_global_config()?;
+
+    let mut failures = 0;
+    for destination in config.link.select_destinations(destinations)? {
+        if let Err(e) = clean_destination(
+            &destination,
+            &config.systems,
+            &systems,
+            all_systems,
+            dry_run,
+        ) {
+            error!("{e}");
+            failures += 1;
         }
     }
 
-    Ok(())
+    check_failures(failures, "clean up")
 }
 
-fn link(systems: Vec<String>, all_systems: bool) -> Result<(), String> {
-    let config = load_global_config()?.link;
+fn link(systems: Vec<String>, all_systems: bool, destinations: &[String]) -> Result<(), Error> {
+    let config = load_global_config()?;
+
+    let sources: Vec<PathBuf> = config
+        .link
+        .expand_sources()?
+        .into_iter()
+        .map(|source| source.path)
+        .collect();
+    let mut failures = 0;
+    for destination in config.link.select_destinations(destinations)? {
+        debug!(
+            "Linking games to {} ({:?})",
+            destination.name, destination.path
+        );
+        if let Err(e) = link_destination(
+            &sources,
+            &destination,
+            &config.systems,
+            &systems,
+            all_systems,
+        ) {
+            error!("{e}");
+            failures += 1;
+        }
+    }
+
+    check_failures(failures, "link")
+}
 
-    for destination in config.expand_destinations() {
-        debug!("Linking games to {destination:?}");
-        if let Err(e) = games::link(&config.expand_source(), &destination, &systems, all_systems) {
-            error!("{e:#?}");
+fn clean_destination(
+    destination: &Destination,
+    catalog: &HashMap<String, System>,
+    systems: &[String],
+    all_systems: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let config = load_link_destination_config(destination, catalog)?;
+    let broken_links = games::clean(&destination.path, &config, systems, all_systems)?;
+    if dry_run {
+        for link in &broken_links {
+            error!("Broken symlink found at {link:?}. Skipping.");
         }
+        return Ok(());
     }
+    games::remove_links(&broken_links)
+}
+
+fn link_destination(
+    sources: &[PathBuf],
+    destination: &Destination,
+    catalog: &HashMap<String, System>,
+    systems: &[String],
+    all_systems: bool,
+) -> Result<(), Error> {
+    let config = load_link_destination_config(destination, catalog)?;
+    let links = games::link(sources, &destination.path, &config, systems, all_systems)?;
+    games::create_links(&links)
+}
 
-    Ok(())
+// Each destination is handled independently, so a failure only means that some of the work wasn't
+// done.
+fn check_failures(failures: usize, action: &str) -> Result<(), Error> {
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(Error::Partial(format!(
+            "Failed to {} {} destination(s)",
+            action, failures
+        )))
+    }
 }
@@ -1,23 +1,15 @@
-mod cli;
-mod compress;
-mod config;
-mod dat;
-mod games;
-mod link;
-mod playlist;
-mod rename;
-mod utils;
-
 use std::process::exit;
 
 use log::error;
 
+use retro::cli;
+
 fn main() {
     exit(match cli::dispatch() {
         Ok(_) => 0,
         Err(e) => {
             error!("{}", e);
-            1
+            e.exit_code()
         }
     });
 }
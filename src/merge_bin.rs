@@ -0,0 +1,507 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, error, warn};
+
+use super::cue::{load_cue_sheet, CueFile, CueIndex, CueSheet, Msf};
+use super::error::Error;
+
+#[derive(Debug, clap::Args)]
+#[command(about = "Merge split bin files")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    merge: Option<MergeArgs>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Commands {
+    #[command(about = "Merge the tracks of a cue sheet into a single bin file")]
+    Merge(MergeArgs),
+
+    #[command(about = "Split a single bin file into one bin file per track")]
+    Split(MergeArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct MergeArgs {
+    #[arg(help = "The cue sheet to read")]
+    source: PathBuf,
+
+    #[arg(
+        help = "Where to place the new files, defaults to the current directory with \" (Merged)\" or \" (Split)\" added to their names so that they can't overwrite the source"
+    )]
+    dest: Option<PathBuf>,
+
+    #[arg(short, long, help = "Force overwriting existing files")]
+    force: bool,
+
+    #[arg(long, help = "Don't write the new files")]
+    dry_run: bool,
+}
+
+impl Args {
+    pub fn dispatch(self) -> Result<(), Error> {
+        let cmd = self
+            .command
+            .or(self.merge.map(Commands::Merge))
+            .ok_or_else(|| Error::from("No command provided"))?;
+        match cmd {
+            Commands::Merge(args) => merge(args.source, args.dest, args.force, args.dry_run),
+            Commands::Split(args) => split(args.source, args.dest, args.force, args.dry_run),
+        }
+    }
+}
+
+// A piece of a bin file that will be copied into a new bin file.
+#[derive(Debug, PartialEq)]
+struct Span {
+    source: PathBuf,
+    offset: u64,
+    length: u64,
+}
+
+fn get_sector_size(sheet: &CueSheet) -> Result<u64, Error> {
+    let mut sizes = sheet.tracks().map(|track| track.mode.sector_size());
+    let sector_size = sizes
+        .next()
+        .ok_or_else(|| Error::from("The cue sheet has no tracks"))?;
+    if sizes.any(|size| size != sector_size) {
+        return Err("Tracks with different sector sizes can't be merged or split".into());
+    }
+    Ok(sector_size)
+}
+
+fn get_file_size(path: &Path) -> Result<u64, Error> {
+    Ok(fs::metadata(path)
+        .map_err(|e| Error::io("Failed to get metadata for", path, e))?
+        .len())
+}
+
+fn get_stem(path: &Path) -> Result<&str, Error> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| {
+            Error::from(format!(
+                "Failed to convert file name {} to UTF-8",
+                path.display()
+            ))
+        })
+}
+
+// Build a cue sheet with a single FILE, moving each track's indexes by the number of frames that
+// come before its original file.
+fn plan_merge(
+    sheet: &CueSheet,
+    directory: &Path,
+    name: &str,
+) -> Result<(CueSheet, Vec<Span>), Error> {
+    let sector_size = get_sector_size(sheet)?;
+
+    let mut merged_file = CueFile::new(name, "BINARY");
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for file in &sheet.files {
+        let source = directory.join(&file.name);
+        let length = get_file_size(&source)?;
+        if length % sector_size != 0 {
+            return Err(format!(
+                "{} isn't a multiple of the {} byte sector size",
+                source.display(),
+                sector_size
+            )
+            .into());
+        }
+
+        let start = Msf((offset / sector_size) as u32);
+        merged_file.rems.extend(file.rems.iter().cloned());
+        merged_file.other.extend(file.other.iter().cloned());
+        for track in &file.tracks {
+            let mut track = track.clone();
+            for index in track.indexes.iter_mut() {
+                index.position = Msf(index.position.frames() + start.frames());
+            }
+            merged_file.tracks.push(track);
+        }
+
+        spans.push(Span {
+            source,
+            offset: 0,
+            length,
+        });
+        offset += length;
+    }
+
+    let merged = CueSheet {
+        rems: sheet.rems.clone(),
+        other: sheet.other.clone(),
+        files: vec![merged_file],
+    };
+    Ok((merged, spans))
+}
+
+// Build a cue sheet with one FILE per track, using Redump's naming. Each track starts at its first
+// index so that pregaps stay with the track they belong to, except for the first track, which
+// always starts at the beginning of the file.
+fn plan_split(
+    sheet: &CueSheet,
+    directory: &Path,
+    stem: &str,
+) -> Result<(CueSheet, Vec<(String, Span)>), Error> {
+    let sector_size = get_sector_size(sheet)?;
+    let [file] = &sheet.files[..] else {
+        return Err("Only cue sheets with a single FILE can be split".into());
+    };
+    let source = directory.join(&file.name);
+    let size = get_file_size(&source)?;
+
+    let mut starts = Vec::new();
+    for (i, track) in file.tracks.iter().enumerate() {
+        let start = if i == 0 {
+            Msf(0)
+        } else {
+            track
+                .indexes
+                .iter()
+                .map(|index| index.position)
+                .min()
+                .ok_or_else(|| format!("Track {:02} has no indexes", track.number))?
+        };
+        starts.push(u64::from(start.frames()) * sector_size);
+    }
+
+    let width = if file.tracks.len() >= 10 { 2 } else { 1 };
+    let mut split = CueSheet {
+        rems: sheet.rems.clone(),
+        other: sheet.other.clone(),
+        files: vec![],
+    };
+    let mut spans = Vec::new();
+    for (i, track) in file.tracks.iter().enumerate() {
+        let offset = starts[i];
+        let end = starts.get(i + 1).copied().unwrap_or(size);
+        if end <= offset || end > size {
+            return Err(format!(
+                "Track {:02} doesn't fit in {}",
+                track.number,
+                source.display()
+            )
+            .into());
+        }
+
+        let name = if file.tracks.len() == 1 {
+            format!("{stem}.bin")
+        } else {
+            format!("{stem} (Track {:0width$}).bin", track.number)
+        };
+        let start_frames = (offset / sector_size) as u32;
+        let mut track = track.clone();
+        track.indexes = track
+            .indexes
+            .iter()
+            .map(|index| CueIndex {
+                number: index.number,
+                position: Msf(index.position.frames() - start_frames),
+            })
+            .collect();
+
+        let mut split_file = CueFile::new(&name, &file.file_type);
+        if i == 0 {
+            split_file.rems = file.rems.clone();
+            split_file.other = file.other.clone();
+        }
+        split_file.tracks.push(track);
+        split.files.push(split_file);
+        spans.push((
+            name,
+            Span {
+                source: source.clone(),
+                offset,
+                length: end - offset,
+            },
+        ));
+    }
+
+    Ok((split, spans))
+}
+
+fn write_spans(destination: &Path, spans: &[&Span]) -> Result<(), Error> {
+    let file =
+        File::create(destination).map_err(|e| Error::io("Failed to create", destination, e))?;
+    let mut writer = BufWriter::new(file);
+
+    for span in spans {
+        let mut source =
+            File::open(&span.source).map_err(|e| Error::io("Failed to open", &span.source, e))?;
+        source
+            .seek(SeekFrom::Start(span.offset))
+            .map_err(|e| Error::io("Failed to seek in", &span.source, e))?;
+        io::copy(&mut BufReader::new(source).take(span.length), &mut writer)
+            .map_err(|e| Error::io("Failed to write to", destination, e))?;
+    }
+
+    writer
+        .flush()
+        .map_err(|e| Error::io("Failed to write to", destination, e))
+}
+
+fn write_cue_sheet(destination: &Path, sheet: &CueSheet) -> Result<(), Error> {
+    fs::write(destination, sheet.to_string())
+        .map_err(|e| Error::io("Failed to write cue sheet", destination, e))
+}
+
+// Paths can point to the same file without being equal, e.g., `./Game.cue` and `Game.cue`, or an
+// absolute and a relative path.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn check_destination(path: &Path, source: &Path, force: bool) -> Result<bool, Error> {
+    if is_same_file(path, source) {
+        return Err(format!("{} would overwrite its source", path.display()).into());
+    }
+    if !force && path.exists() {
+        warn!("{} exists. Skipping.", path.display());
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Without a destination, the new files go in the current directory, which is usually the source's,
+// so they get a suffix to keep them from replacing the source's files.
+fn get_output(
+    source: &Path,
+    dest: Option<PathBuf>,
+    suffix: &str,
+) -> Result<(PathBuf, String), Error> {
+    let stem = get_stem(source)?;
+    Ok(match dest {
+        Some(dest) => (dest, stem.to_string()),
+        None => (PathBuf::new(), format!("{stem} ({suffix})")),
+    })
+}
+
+fn merge(source: PathBuf, dest: Option<PathBuf>, force: bool, dry_run: bool) -> Result<(), Error> {
+    let (output_path, stem) = get_output(&source, dest, "Merged")?;
+    debug!("Merging the tracks of {source:?} into {output_path:?}");
+
+    let sheet = load_cue_sheet(&source)?;
+    if sheet.files.len() < 2 {
+        warn!("{} already uses a single file. Skipping.", source.display());
+        return Ok(());
+    }
+
+    let bin = output_path.join(format!("{stem}.bin"));
+    let cue_sheet = output_path.join(format!("{stem}.cue"));
+    let directory = source.parent().unwrap_or(Path::new(""));
+    let (merged, spans) = plan_merge(&sheet, directory, &format!("{stem}.bin"))?;
+
+    if spans.iter().any(|span| is_same_file(&span.source, &bin)) {
+        return Err(format!("{} would overwrite one of its tracks", bin.display()).into());
+    }
+    if !check_destination(&cue_sheet, &source, force)? || !check_destination(&bin, &source, force)?
+    {
+        return Ok(());
+    }
+
+    if dry_run {
+        error!("Would merge {} files into {}", spans.len(), bin.display());
+        debug!("{merged}");
+        return Ok(());
+    }
+
+    let spans: Vec<&Span> = spans.iter().collect();
+    write_spans(&bin, &spans)?;
+    write_cue_sheet(&cue_sheet, &merged)?;
+    error!("Merged {} into {}", source.display(), bin.display());
+
+    Ok(())
+}
+
+fn split(source: PathBuf, dest: Option<PathBuf>, force: bool, dry_run: bool) -> Result<(), Error> {
+    let (output_path, stem) = get_output(&source, dest, "Split")?;
+    debug!("Splitting the tracks of {source:?} into {output_path:?}");
+
+    let sheet = load_cue_sheet(&source)?;
+    let cue_sheet = output_path.join(format!("{stem}.cue"));
+    let directory = source.parent().unwrap_or(Path::new(""));
+    let (split, spans) = plan_split(&sheet, directory, &stem)?;
+
+    if !check_destination(&cue_sheet, &source, force)? {
+        return Ok(());
+    }
+    for (name, span) in &spans {
+        let bin = output_path.join(name);
+        if is_same_file(&bin, &span.source) {
+            return Err(format!("{} would overwrite its source", bin.display()).into());
+        }
+        if !check_destination(&bin, &source, force)? {
+            return Ok(());
+        }
+    }
+
+    if dry_run {
+        for (name, _) in &spans {
+            error!("Would create {}", output_path.join(name).display());
+        }
+        debug!("{split}");
+        return Ok(());
+    }
+
+    for (name, span) in &spans {
+        let bin = output_path.join(name);
+        write_spans(&bin, &[span])?;
+        error!("Created {}", bin.display());
+    }
+    write_cue_sheet(&cue_sheet, &split)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, read, write};
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Self {
+            Self {
+                root: TempDir::new("tmp").unwrap(),
+            }
+        }
+
+        fn teardown(self) {
+            self.root.close().unwrap();
+        }
+    }
+
+    const SPLIT: &str = r#"FILE "Game (Track 1).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Game (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+"#;
+
+    const MERGED: &str = r#"FILE "Game.bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 00:00:04
+    INDEX 01 00:02:04
+"#;
+
+    #[test_context(Context)]
+    #[test]
+    fn merge_tracks(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), SPLIT).unwrap();
+        write(root.join("Game (Track 1).bin"), vec![1; 2352 * 4]).unwrap();
+        write(root.join("Game (Track 2).bin"), vec![2; 2352 * 200]).unwrap();
+        let output = root.join("merged");
+        fs::create_dir(&output).unwrap();
+
+        merge(root.join("Game.cue"), Some(output.clone()), false, false).unwrap();
+
+        assert_eq!(fs::read_to_string(output.join("Game.cue")).unwrap(), MERGED);
+        let bin = read(output.join("Game.bin")).unwrap();
+        assert_eq!(bin.len(), 2352 * 204);
+        assert_eq!(bin[2352 * 4 - 1], 1);
+        assert_eq!(bin[2352 * 4], 2);
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn split_tracks(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), MERGED).unwrap();
+        let mut bin = vec![1; 2352 * 4];
+        bin.extend(vec![2; 2352 * 200]);
+        write(root.join("Game.bin"), bin).unwrap();
+        let output = root.join("split");
+        fs::create_dir(&output).unwrap();
+
+        split(root.join("Game.cue"), Some(output.clone()), false, false).unwrap();
+
+        assert_eq!(fs::read_to_string(output.join("Game.cue")).unwrap(), SPLIT);
+        assert_eq!(
+            read(output.join("Game (Track 1).bin")).unwrap(),
+            vec![1; 2352 * 4]
+        );
+        assert_eq!(
+            read(output.join("Game (Track 2).bin")).unwrap(),
+            vec![2; 2352 * 200]
+        );
+    }
+
+    #[test]
+    fn get_output_adds_suffix_without_destination() {
+        let source = Path::new("games").join("Game.cue");
+        assert_eq!(
+            get_output(&source, None, "Merged").unwrap(),
+            (PathBuf::new(), "Game (Merged)".to_string())
+        );
+        assert_eq!(
+            get_output(&source, Some(PathBuf::from("out")), "Merged").unwrap(),
+            (PathBuf::from("out"), "Game".to_string())
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn merge_refuses_mixed_sector_sizes(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(
+            root.join("Game.cue"),
+            SPLIT.replace("MODE2/2352", "MODE1/2048"),
+        )
+        .unwrap();
+        write(root.join("Game (Track 1).bin"), vec![0; 2048]).unwrap();
+        write(root.join("Game (Track 2).bin"), vec![0; 2352]).unwrap();
+
+        let sheet = load_cue_sheet(&root.join("Game.cue")).unwrap();
+        assert!(plan_merge(&sheet, root, "Game.bin").is_err());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn merge_refuses_to_overwrite_source(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), SPLIT).unwrap();
+        write(root.join("Game (Track 1).bin"), vec![0; 2352]).unwrap();
+        write(root.join("Game (Track 2).bin"), vec![0; 2352]).unwrap();
+
+        assert!(merge(root.join("Game.cue"), Some(root.to_path_buf()), true, false).is_err());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn split_refuses_to_overwrite_source_through_another_path(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.cue"), MERGED).unwrap();
+        write(root.join("Game.bin"), vec![0; 2352 * 200]).unwrap();
+
+        create_dir(root.join("tracks")).unwrap();
+
+        let destination = root.join("tracks").join("..");
+        let error = split(root.join("Game.cue"), Some(destination), true, false).unwrap_err();
+        assert!(error.to_string().ends_with("would overwrite its source"));
+        assert_eq!(read(root.join("Game.cue")).unwrap(), MERGED.as_bytes());
+    }
+}
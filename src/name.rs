@@ -0,0 +1,636 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use log::error;
+
+use regex::Regex;
+
+use super::config::load_optional_config_recursively;
+use super::error::Error;
+
+static LANGUAGES_PATTERN: OnceLock<Regex> = OnceLock::new();
+static DISC_PATTERN: OnceLock<Regex> = OnceLock::new();
+static DATE_PATTERN: OnceLock<Regex> = OnceLock::new();
+static TITLE_VERSION_PATTERN: OnceLock<Regex> = OnceLock::new();
+static REVISION_PATTERN: OnceLock<Regex> = OnceLock::new();
+static MEDIA_PATTERN: OnceLock<Regex> = OnceLock::new();
+static FLAG_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, clap::Args)]
+#[command(about = "Work with game names")]
+pub struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Commands {
+    #[command(about = "Convert GoodTools and TOSEC names to No-Intro names")]
+    Convert(ConvertArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ConvertArgs {
+    #[arg(required = true, help = "The names to convert")]
+    names: Vec<String>,
+}
+
+impl Args {
+    pub fn dispatch(self) -> Result<(), Error> {
+        match self.command {
+            Commands::Convert(args) => {
+                let config = load_name_config(Path::new("."))?;
+                for name in args.names {
+                    error!("{}", convert_name(&name, &config));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// GoodTools codes (e.g., `(U)`) and TOSEC codes (e.g., `(US)` or `(US-EU)`, split on `-`).
+const REGION_CODES: [(&str, &str); 46] = [
+    ("A", "Australia"),
+    ("As", "Asia"),
+    ("B", "Brazil"),
+    ("C", "China"),
+    ("Ch", "China"),
+    ("E", "Europe"),
+    ("F", "France"),
+    ("G", "Germany"),
+    ("Gr", "Greece"),
+    ("HK", "Hong Kong"),
+    ("I", "Italy"),
+    ("J", "Japan"),
+    ("JU", "Japan, USA"),
+    ("JUE", "World"),
+    ("K", "Korea"),
+    ("Nl", "Netherlands"),
+    ("No", "Norway"),
+    ("R", "Russia"),
+    ("S", "Spain"),
+    ("Sw", "Sweden"),
+    ("U", "USA"),
+    ("UE", "USA, Europe"),
+    ("UK", "UK"),
+    ("Unk", "Unknown"),
+    ("W", "World"),
+    ("AU", "Australia"),
+    ("BR", "Brazil"),
+    ("CA", "Canada"),
+    ("CN", "China"),
+    ("DE", "Germany"),
+    ("DK", "Denmark"),
+    ("ES", "Spain"),
+    ("EU", "Europe"),
+    ("FI", "Finland"),
+    ("FR", "France"),
+    ("GB", "UK"),
+    ("IT", "Italy"),
+    ("JP", "Japan"),
+    ("KR", "Korea"),
+    ("NL", "Netherlands"),
+    ("NO", "Norway"),
+    ("PL", "Poland"),
+    ("PT", "Portugal"),
+    ("SE", "Sweden"),
+    ("TW", "Taiwan"),
+    ("US", "USA"),
+];
+
+// TOSEC language codes, e.g., `(en)` or `(en-fr)`.
+const LANGUAGE_CODES: [(&str, &str); 17] = [
+    ("cs", "Cs"),
+    ("da", "Da"),
+    ("de", "De"),
+    ("el", "El"),
+    ("en", "En"),
+    ("es", "Es"),
+    ("fi", "Fi"),
+    ("fr", "Fr"),
+    ("it", "It"),
+    ("ja", "Ja"),
+    ("ko", "Ko"),
+    ("nl", "Nl"),
+    ("no", "No"),
+    ("pl", "Pl"),
+    ("pt", "Pt"),
+    ("ru", "Ru"),
+    ("sv", "Sv"),
+];
+
+// GoodTools and TOSEC dump flags, keyed by the letters they start with (e.g., `[a2]` or `[h1C]`).
+// Verified dumps are the only kind No-Intro lists, so `[!]` is dropped.
+const FLAG_CODES: [(&str, &str); 15] = [
+    ("!", ""),
+    ("a", "(Alt)"),
+    ("b", "[b]"),
+    ("cr", "(Cracked)"),
+    ("f", "(Fixed)"),
+    ("h", "(Hack)"),
+    ("m", "(Modified)"),
+    ("o", "(Overdump)"),
+    ("p", "(Pirate)"),
+    ("T", "(Translated)"),
+    ("t", "(Trainer)"),
+    ("tr", "(Translated)"),
+    ("u", "(Underdump)"),
+    ("v", "(Virus)"),
+    ("x", "[b]"),
+];
+
+// Other tags that are spelled differently, without their parentheses.
+const TAG_CODES: [(&str, &str); 5] = [
+    ("alpha", "Alpha"),
+    ("beta", "Beta"),
+    ("preview", "Preview"),
+    ("proto", "Proto"),
+    ("Prototype", "Proto"),
+];
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    #[serde(default)]
+    name: NameConfig,
+}
+
+// Extra mappings used when converting names, e.g., `[name.regions]` with `"Fc" = "Canada"`. These
+// take precedence over the built-in mappings, and mapping a code to an empty string drops it.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct NameConfig {
+    #[serde(default)]
+    pub regions: HashMap<String, String>,
+    #[serde(default)]
+    pub languages: HashMap<String, String>,
+    #[serde(default)]
+    pub flags: HashMap<String, String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+fn lookup(
+    overrides: &HashMap<String, String>,
+    defaults: &[(&str, &str)],
+    code: &str,
+) -> Option<String> {
+    overrides.get(code).cloned().or_else(|| {
+        defaults
+            .iter()
+            .find(|(key, _)| *key == code)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+impl NameConfig {
+    fn convert_region(&self, code: &str) -> Option<String> {
+        if is_region(code) {
+            return Some(code.to_string());
+        }
+        if let Some(region) = lookup(&self.regions, &REGION_CODES, code) {
+            return Some(region);
+        }
+        let regions = code
+            .split('-')
+            .map(|code| lookup(&self.regions, &REGION_CODES, code))
+            .collect::<Option<Vec<_>>>()?;
+        Some(regions.join(", "))
+    }
+
+    fn convert_languages(&self, code: &str) -> Option<String> {
+        if is_languages(code) {
+            return Some(code.to_string());
+        }
+        let languages = code
+            .split('-')
+            .map(|code| lookup(&self.languages, &LANGUAGE_CODES, code))
+            .collect::<Option<Vec<_>>>()?;
+        Some(languages.join(","))
+    }
+
+    fn convert_flag(&self, flag: &str) -> Option<String> {
+        let code = FLAG_PATTERN
+            .get_or_init(|| Regex::new(r"^(!|[A-Za-z]+)").expect("Failed to compile regex pattern"))
+            .find(flag)?
+            .as_str();
+        lookup(&self.flags, &FLAG_CODES, code)
+    }
+
+    fn convert_tag(&self, tag: &str) -> Option<String> {
+        if let Some(tag) = lookup(&self.tags, &TAG_CODES, tag) {
+            return Some(tag);
+        }
+
+        // GoodTools revisions (e.g., `(REV01)` or `(PRG1)`) and versions (e.g., `(V1.1)`), and the
+        // number of languages in a GoodTools multi-language release (e.g., `(M3)`).
+        let revision = REVISION_PATTERN.get_or_init(|| {
+            Regex::new(r"^(?:(?:REV|PRG)0*(?<rev>\d+)|V(?<version>\d+(?:\.\d+)*)|M\d+)$")
+                .expect("Failed to compile regex pattern")
+        });
+        if let Some(capture) = revision.captures(tag) {
+            return Some(if let Some(rev) = capture.name("rev") {
+                format!("Rev {}", rev.as_str())
+            } else if let Some(version) = capture.name("version") {
+                format!("v{}", version.as_str())
+            } else {
+                String::new()
+            });
+        }
+
+        // TOSEC media, e.g., `(Disc 1 of 2)`.
+        let media = MEDIA_PATTERN.get_or_init(|| {
+            Regex::new(r"^(?<media>(?:Disc|Disk|Side|Tape|Part) \w+) of \d+$")
+                .expect("Failed to compile regex pattern")
+        });
+        media
+            .captures(tag)
+            .and_then(|capture| capture.name("media"))
+            .map(|media| media.as_str().to_string())
+    }
+}
+
+pub fn load_name_config(root: &Path) -> Result<NameConfig, Error> {
+    let config: Config = load_optional_config_recursively(root)?;
+    Ok(config.name)
+}
+
+pub const REGIONS: [&str; 31] = [
+    "World",
+    "USA",
+    "Europe",
+    "Japan",
+    "Asia",
+    "Australia",
+    "Brazil",
+    "Canada",
+    "China",
+    "Denmark",
+    "Finland",
+    "France",
+    "Germany",
+    "Greece",
+    "Hong Kong",
+    "India",
+    "Italy",
+    "Korea",
+    "Latin America",
+    "Mexico",
+    "Netherlands",
+    "Norway",
+    "Poland",
+    "Portugal",
+    "Russia",
+    "Scandinavia",
+    "Spain",
+    "Sweden",
+    "Taiwan",
+    "UK",
+    "Unknown",
+];
+
+// The fields of a title that can be looked up by name.
+pub const FIELDS: [&str; 5] = ["title", "region", "languages", "disc", "tags"];
+
+// The parts of a No-Intro style name, e.g., `Game, The (USA, Europe) (En,Fr) (Disc 1) [b]`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Title {
+    pub title: String,
+    pub region: Option<String>,
+    pub languages: Option<String>,
+    pub disc: Option<String>,
+    // Every tag in the name, in order and including their parentheses or brackets.
+    pub tags: Vec<String>,
+}
+
+impl Title {
+    pub fn get_field(&self, name: &str) -> Option<String> {
+        match name {
+            "title" => Some(self.title.clone()),
+            "region" => self.region.clone(),
+            "languages" => self.languages.clone(),
+            "disc" => self.disc.clone(),
+            "tags" => Some(self.tags.join(" ")),
+            _ => None,
+        }
+    }
+}
+
+fn is_region(tag: &str) -> bool {
+    tag.split(", ").all(|region| REGIONS.contains(&region))
+}
+
+fn is_languages(tag: &str) -> bool {
+    LANGUAGES_PATTERN
+        .get_or_init(|| {
+            Regex::new(r"^[A-Z][a-z](-[A-Z][a-z])?(,[A-Z][a-z](-[A-Z][a-z])?)*$")
+                .expect("Failed to compile regex pattern")
+        })
+        .is_match(tag)
+}
+
+fn get_disc(tag: &str) -> Option<String> {
+    DISC_PATTERN
+        .get_or_init(|| {
+            Regex::new(r"^Disc (?<disc>\w+)$").expect("Failed to compile regex pattern")
+        })
+        .captures(tag)
+        .and_then(|capture| capture.name("disc"))
+        .map(|disc| disc.as_str().to_string())
+}
+
+// Split a name into its leading title and the tags that follow it.
+pub fn split_tags(name: &str) -> (&str, Vec<&str>) {
+    // Only a parenthesis or bracket that starts a word begins a tag, e.g., `Game(s) (USA)`.
+    let start = name
+        .char_indices()
+        .find(|(i, c)| (*c == '(' || *c == '[') && (*i == 0 || name[..*i].ends_with(' ')))
+        .map(|(i, _)| i);
+    let Some(start) = start else {
+        return (name.trim(), vec![]);
+    };
+
+    let mut tags = Vec::new();
+    let mut rest = &name[start..];
+    loop {
+        rest = rest.trim_start();
+        let close = match rest.chars().next() {
+            Some('(') => ')',
+            Some('[') => ']',
+            _ => break,
+        };
+        let Some(end) = rest.find(close) else {
+            break;
+        };
+        tags.push(&rest[..=end]);
+        rest = &rest[end + 1..];
+    }
+
+    (name[..start].trim(), tags)
+}
+
+pub fn parse_title(stem: &str) -> Title {
+    let (title, tags) = split_tags(stem);
+
+    let mut parsed = Title {
+        title: title.to_string(),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..Default::default()
+    };
+    for tag in tags {
+        let Some(inner) = tag.strip_prefix('(').and_then(|tag| tag.strip_suffix(')')) else {
+            continue;
+        };
+        if parsed.region.is_none() && is_region(inner) {
+            parsed.region = Some(inner.to_string());
+        } else if parsed.languages.is_none() && is_languages(inner) {
+            parsed.languages = Some(inner.to_string());
+        } else if let Some(disc) = get_disc(inner) {
+            parsed.disc = Some(disc);
+        }
+    }
+    parsed
+}
+
+// Get the name a frontend should show for a game. Articles that were moved to the end of the title
+// for sorting are moved back, e.g., `Game, The - Subtitle (USA)` becomes `The Game - Subtitle`.
+pub fn get_display_name(stem: &str) -> String {
+    let title = parse_title(stem).title;
+    let (main, subtitle) = match title.split_once(" - ") {
+        Some((main, subtitle)) => (main, Some(subtitle)),
+        None => (title.as_str(), None),
+    };
+    let main = ["The", "A", "An"]
+        .iter()
+        .find_map(|article| {
+            main.strip_suffix(&format!(", {article}"))
+                .map(|rest| format!("{article} {rest}"))
+        })
+        .unwrap_or_else(|| main.to_string());
+    match subtitle {
+        Some(subtitle) => format!("{main} - {subtitle}"),
+        None => main,
+    }
+}
+
+fn is_date(tag: &str) -> bool {
+    DATE_PATTERN
+        .get_or_init(|| {
+            Regex::new(r"^\(\d[\dx]{3}(-\d[\dx](-\d[\dx])?)?\)$")
+                .expect("Failed to compile regex pattern")
+        })
+        .is_match(tag)
+}
+
+// Convert a GoodTools (e.g., `Game (U) [!]`) or TOSEC (e.g., `Game (1992)(Publisher)(US)`) name to a
+// No-Intro name. Tags that can't be converted are kept as they are, so No-Intro names are left
+// unchanged.
+pub fn convert_name(stem: &str, config: &NameConfig) -> String {
+    let (title, mut tags) = split_tags(stem);
+
+    // TOSEC names always start with a date and a publisher, neither of which No-Intro uses.
+    if tags.first().is_some_and(|tag| is_date(tag)) {
+        tags.drain(..tags.len().min(2));
+    }
+
+    let mut title = title.to_string();
+    let mut region = None;
+    let mut languages = None;
+    let mut others = Vec::new();
+    let mut flags = Vec::new();
+
+    // TOSEC puts versions in the title, e.g., `Game v1.1 (1992)(Publisher)`.
+    let version = TITLE_VERSION_PATTERN.get_or_init(|| {
+        Regex::new(r" v(?<version>\d+(?:\.\d+)*)$").expect("Failed to compile regex pattern")
+    });
+    if let Some(capture) = version.captures(&title) {
+        others.push(format!("(v{})", &capture["version"]));
+        let start = capture.get(0).map_or(title.len(), |m| m.start());
+        title.truncate(start);
+    }
+
+    for tag in tags {
+        if let Some(flag) = tag.strip_prefix('[').and_then(|tag| tag.strip_suffix(']')) {
+            match config.convert_flag(flag) {
+                Some(flag) if flag.starts_with('[') => flags.push(flag),
+                Some(flag) if !flag.is_empty() => others.push(flag),
+                Some(_) => {}
+                None => flags.push(tag.to_string()),
+            }
+            continue;
+        }
+
+        let Some(inner) = tag.strip_prefix('(').and_then(|tag| tag.strip_suffix(')')) else {
+            others.push(tag.to_string());
+            continue;
+        };
+        if region.is_none() {
+            region = config.convert_region(inner);
+            if region.is_some() {
+                continue;
+            }
+        }
+        if languages.is_none() {
+            languages = config.convert_languages(inner);
+            if languages.is_some() {
+                continue;
+            }
+        }
+        match config.convert_tag(inner) {
+            Some(tag) if !tag.is_empty() => others.push(format!("({})", tag)),
+            Some(_) => {}
+            None => others.push(tag.to_string()),
+        }
+    }
+
+    let mut parts = vec![title];
+    parts.extend(region.map(|region| format!("({})", region)));
+    parts.extend(languages.map(|languages| format!("({})", languages)));
+    for tag in others.into_iter().chain(flags) {
+        if !parts.contains(&tag) {
+            parts.push(tag);
+        }
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_title_with_all_fields() {
+        let title = parse_title("Game, The (USA, Europe) (En,Fr,De) (Disc 2) (Rev 1) [b]");
+        assert_eq!(
+            title,
+            Title {
+                title: "Game, The".to_string(),
+                region: Some("USA, Europe".to_string()),
+                languages: Some("En,Fr,De".to_string()),
+                disc: Some("2".to_string()),
+                tags: vec![
+                    "(USA, Europe)".to_string(),
+                    "(En,Fr,De)".to_string(),
+                    "(Disc 2)".to_string(),
+                    "(Rev 1)".to_string(),
+                    "[b]".to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_title_without_tags() {
+        assert_eq!(
+            parse_title("Game"),
+            Title {
+                title: "Game".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_title_keeps_parentheses_inside_the_title() {
+        let title = parse_title("Game(s) of Chance (Japan)");
+        assert_eq!(title.title, "Game(s) of Chance");
+        assert_eq!(title.region, Some("Japan".to_string()));
+    }
+
+    #[test]
+    fn parse_title_ignores_unknown_regions() {
+        let title = parse_title("Game (Proto) (Atlantis)");
+        assert_eq!(title.region, None);
+        assert_eq!(title.tags, &["(Proto)", "(Atlantis)"]);
+    }
+
+    #[test]
+    fn title_get_field() {
+        let title = parse_title("Game (USA) (Disc A)");
+        assert_eq!(title.get_field("title"), Some("Game".to_string()));
+        assert_eq!(title.get_field("region"), Some("USA".to_string()));
+        assert_eq!(title.get_field("languages"), None);
+        assert_eq!(title.get_field("disc"), Some("A".to_string()));
+        assert_eq!(title.get_field("unknown"), None);
+    }
+
+    #[test]
+    fn convert_name_from_goodtools() {
+        let config = NameConfig::default();
+        assert_eq!(convert_name("Game (U) [!]", &config), "Game (USA)");
+        assert_eq!(
+            convert_name("Game, The (JU) (REV01) [a1][a2]", &config),
+            "Game, The (Japan, USA) (Rev 1) (Alt)"
+        );
+        assert_eq!(
+            convert_name("Game (E) (M3) (V1.1) [b1]", &config),
+            "Game (Europe) (v1.1) [b]"
+        );
+        assert_eq!(
+            convert_name("Game (U) (Prototype) [T+Fre]", &config),
+            "Game (USA) (Proto) (Translated)"
+        );
+    }
+
+    #[test]
+    fn convert_name_from_tosec() {
+        let config = NameConfig::default();
+        assert_eq!(
+            convert_name("Game (1992)(Publisher)(US)(en)[!]", &config),
+            "Game (USA) (En)"
+        );
+        assert_eq!(
+            convert_name(
+                "Game v1.1 (1995-03)(Publisher)(US-EU)(en-fr)(Disc 1 of 2)[cr]",
+                &config
+            ),
+            "Game (USA, Europe) (En,Fr) (v1.1) (Disc 1) (Cracked)"
+        );
+        assert_eq!(
+            convert_name("Game (19xx)(Publisher)(beta)", &config),
+            "Game (Beta)"
+        );
+    }
+
+    #[test]
+    fn convert_name_leaves_no_intro_names_unchanged() {
+        let name = "Game, The (USA, Europe) (En,Fr) (Rev 1) (Disc 2) [b]";
+        assert_eq!(convert_name(name, &NameConfig::default()), name);
+    }
+
+    #[test]
+    fn convert_name_with_config() {
+        let config = NameConfig {
+            regions: HashMap::from([("Fc".to_string(), "Canada".to_string())]),
+            flags: HashMap::from([("a".to_string(), "".to_string())]),
+            tags: HashMap::from([("Unl".to_string(), "Unlicensed".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_name("Game (Fc) (Unl) [a1]", &config),
+            "Game (Canada) (Unlicensed)"
+        );
+    }
+
+    #[test]
+    fn convert_name_keeps_unknown_tags() {
+        assert_eq!(
+            convert_name("Game (U) (Sega) [x2]", &NameConfig::default()),
+            "Game (USA) (Sega) [b]"
+        );
+        assert_eq!(
+            convert_name("Game (Whatever) [zz]", &NameConfig::default()),
+            "Game (Whatever) [zz]"
+        );
+    }
+
+    #[test]
+    fn get_display_name_moves_articles_back() {
+        assert_eq!(get_display_name("Game, The (USA)"), "The Game");
+        assert_eq!(
+            get_display_name("Game, A - Subtitle (Europe) (Disc 1)"),
+            "A Game - Subtitle"
+        );
+        assert_eq!(get_display_name("Theme Park (USA)"), "Theme Park");
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, error, warn};
+
+use super::error::Error;
+use super::utils::get_from_env;
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+// The contents of a file before and after it's rewritten. The path is where the file lives once
+// all of the renames in a plan have been applied.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Rewrite {
+    pub path: PathBuf,
+    pub before: String,
+    pub after: String,
+}
+
+// Every change that makes up a single rename operation. Plans are built up front so that they can
+// be checked before anything is touched, applied as a batch, and stored in the journal so they can
+// be undone.
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Plan {
+    pub renames: Vec<Rename>,
+    pub rewrites: Vec<Rewrite>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.renames.is_empty() && self.rewrites.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Plan) {
+        self.renames.extend(other.renames);
+        self.rewrites.extend(other.rewrites);
+    }
+
+    // Build the plan that reverts this one.
+    pub fn inverse(&self) -> Plan {
+        let renames = self
+            .renames
+            .iter()
+            .rev()
+            .map(|rename| Rename {
+                from: rename.to.clone(),
+                to: rename.from.clone(),
+            })
+            .collect();
+        let rewrites = self
+            .rewrites
+            .iter()
+            .map(|rewrite| Rewrite {
+                path: self
+                    .renames
+                    .iter()
+                    .find(|rename| rename.to == rewrite.path)
+                    .map_or(rewrite.path.clone(), |rename| rename.from.clone()),
+                before: rewrite.after.clone(),
+                after: rewrite.before.clone(),
+            })
+            .collect();
+        Plan { renames, rewrites }
+    }
+
+    // Make sure the plan can be applied without overwriting anything. Every problem is reported at
+    // once so they can all be fixed before trying again. Targets are compared without case, since
+    // they'd replace each other on a case-insensitive filesystem.
+    pub fn check(&self) -> Result<(), Error> {
+        let sources: HashSet<_> = self
+            .renames
+            .iter()
+            .filter_map(|rename| get_file_id(&rename.from))
+            .collect();
+        let mut targets = HashSet::new();
+        let mut problems = Vec::new();
+
+        for rename in &self.renames {
+            if !rename.from.exists() {
+                problems.push(format!("{} doesn't exist", rename.from.display()));
+            }
+            if !targets.insert(rename.to.to_string_lossy().to_lowercase()) {
+                problems.push(format!(
+                    "More than one file would be renamed to {}",
+                    rename.to.display()
+                ));
+            } else if get_file_id(&rename.to).is_some_and(|target| !sources.contains(&target)) {
+                problems.push(format!("{} already exists", rename.to.display()));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n").into())
+        }
+    }
+}
+
+// What a path leads to, which is the same for every path to a file, e.g., `Game.ISO` and `Game.iso`
+// on a case-insensitive filesystem.
+#[cfg(unix)]
+fn get_file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn get_file_id(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path).ok()
+}
+
+fn get_temporary_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.retro-rename"))
+}
+
+// Undo the renames that were already applied, most recent first.
+fn roll_back(applied: &[(PathBuf, PathBuf)]) {
+    for (from, to) in applied.iter().rev() {
+        if let Err(e) = fs::rename(to, from) {
+            error!(
+                "Failed to roll back {} to {}: {}",
+                to.display(),
+                from.display(),
+                e
+            );
+        }
+    }
+}
+
+// Apply a plan as a single batch. Every file is first moved to a temporary name so that renames
+// within the batch (e.g., swapping two names) can't clobber each other. If anything fails, the
+// changes that were already made are reverted.
+pub fn apply_plan(plan: &Plan) -> Result<(), Error> {
+    let mut applied: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    let steps = plan
+        .renames
+        .iter()
+        .map(|rename| (rename.from.clone(), get_temporary_path(&rename.from)))
+        .chain(
+            plan.renames
+                .iter()
+                .map(|rename| (get_temporary_path(&rename.from), rename.to.clone())),
+        );
+    for (from, to) in steps {
+        if let Err(e) = fs::rename(&from, &to) {
+            roll_back(&applied);
+            return Err(format!(
+                "Failed to rename {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            )
+            .into());
+        }
+        applied.push((from, to));
+    }
+
+    for (i, rewrite) in plan.rewrites.iter().enumerate() {
+        if let Err(e) = fs::write(&rewrite.path, &rewrite.after) {
+            for previous in &plan.rewrites[..i] {
+                if let Err(e) = fs::write(&previous.path, &previous.before) {
+                    error!("Failed to roll back {}: {}", previous.path.display(), e);
+                }
+            }
+            roll_back(&applied);
+            return Err(format!("Failed to write to {}: {}", rewrite.path.display(), e).into());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn log_plan(plan: &Plan, dry_run: bool) {
+    let prefix = if dry_run { "Would rename" } else { "Renamed" };
+    for rename in &plan.renames {
+        error!(
+            "{prefix} {} to {}",
+            rename.from.display(),
+            rename.to.display()
+        );
+    }
+    let prefix = if dry_run { "Would rewrite" } else { "Rewrote" };
+    for rewrite in &plan.rewrites {
+        error!("{prefix} {}", rewrite.path.display());
+    }
+}
+
+// Journals are kept next to the config unless the environment variable says otherwise.
+pub fn get_journal_path(name: &str, variable: &str) -> Result<PathBuf, Error> {
+    if let Ok(path) = get_from_env(variable) {
+        return Ok(PathBuf::from(path));
+    }
+    confy::get_configuration_file_path("retro", name)
+        .map_err(|e| Error::Config(format!("Failed to find journal: {}", e)))
+}
+
+// Check and apply a plan, recording it in the journal so that it can be undone.
+pub fn run_plan(plan: &Plan, journal: &Path, dry_run: bool) -> Result<(), Error> {
+    if plan.is_empty() {
+        warn!("Nothing to rename. Skipping.");
+        return Ok(());
+    }
+
+    plan.check()?;
+    if dry_run {
+        log_plan(plan, dry_run);
+        return Ok(());
+    }
+
+    apply_plan(plan)?;
+    log_plan(plan, dry_run);
+
+    debug!("Recording rename in {journal:?}");
+    confy::store_path(journal, plan).map_err(|e| {
+        Error::Other(format!(
+            "Failed to write journal {}: {}",
+            journal.display(),
+            e
+        ))
+    })
+}
+
+pub fn undo(journal: &Path, dry_run: bool) -> Result<(), Error> {
+    if !journal.is_file() {
+        return Err("Nothing to undo".into());
+    }
+    let plan: Plan = confy::load_path(journal).map_err(|e| {
+        Error::Other(format!(
+            "Failed to read journal {}: {}",
+            journal.display(),
+            e
+        ))
+    })?;
+    let inverse = plan.inverse();
+
+    inverse.check()?;
+    for rewrite in &plan.rewrites {
+        let current = fs::read_to_string(&rewrite.path)
+            .map_err(|e| Error::io("Failed to read", &rewrite.path, e))?;
+        if current != rewrite.after {
+            return Err(format!(
+                "{} has changed since it was renamed",
+                rewrite.path.display()
+            )
+            .into());
+        }
+    }
+
+    if dry_run {
+        log_plan(&inverse, dry_run);
+        return Ok(());
+    }
+
+    apply_plan(&inverse)?;
+    log_plan(&inverse, dry_run);
+
+    fs::remove_file(journal).map_err(|e| Error::io("Failed to remove journal", journal, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Self {
+            Self {
+                root: TempDir::new("tmp").unwrap(),
+            }
+        }
+
+        fn teardown(self) {
+            self.root.close().unwrap();
+        }
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_check_finds_collisions(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a"), "").unwrap();
+        write(root.join("b"), "").unwrap();
+        let plan = Plan {
+            renames: vec![Rename {
+                from: root.join("a"),
+                to: root.join("b"),
+            }],
+            rewrites: vec![],
+        };
+        assert_eq!(
+            plan.check().unwrap_err().to_string(),
+            format!("{} already exists", root.join("b").display())
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_check_finds_duplicate_targets(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a"), "").unwrap();
+        write(root.join("b"), "").unwrap();
+        let plan = Plan {
+            renames: vec![
+                Rename {
+                    from: root.join("a"),
+                    to: root.join("c"),
+                },
+                Rename {
+                    from: root.join("b"),
+                    to: root.join("c"),
+                },
+            ],
+            rewrites: vec![],
+        };
+        assert!(plan.check().is_err());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_check_allows_renames_to_the_same_file(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Game.ISO"), "").unwrap();
+        // Both names lead to the same file, as they would on a case-insensitive filesystem.
+        fs::hard_link(root.join("Game.ISO"), root.join("Game.iso")).unwrap();
+        let plan = Plan {
+            renames: vec![Rename {
+                from: root.join("Game.ISO"),
+                to: root.join("Game.iso"),
+            }],
+            rewrites: vec![],
+        };
+        plan.check().unwrap();
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_check_finds_targets_that_only_differ_by_case(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a"), "").unwrap();
+        write(root.join("b"), "").unwrap();
+        let plan = Plan {
+            renames: vec![
+                Rename {
+                    from: root.join("a"),
+                    to: root.join("A.iso"),
+                },
+                Rename {
+                    from: root.join("b"),
+                    to: root.join("a.iso"),
+                },
+            ],
+            rewrites: vec![],
+        };
+        assert_eq!(
+            plan.check().unwrap_err().to_string(),
+            format!(
+                "More than one file would be renamed to {}",
+                root.join("a.iso").display()
+            )
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn apply_plan_swaps_names(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a"), "a").unwrap();
+        write(root.join("b"), "b").unwrap();
+        let plan = Plan {
+            renames: vec![
+                Rename {
+                    from: root.join("a"),
+                    to: root.join("b"),
+                },
+                Rename {
+                    from: root.join("b"),
+                    to: root.join("a"),
+                },
+            ],
+            rewrites: vec![],
+        };
+
+        plan.check().unwrap();
+        apply_plan(&plan).unwrap();
+        assert_eq!(read_to_string(root.join("a")).unwrap(), "b");
+        assert_eq!(read_to_string(root.join("b")).unwrap(), "a");
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn apply_plan_rolls_back_on_failure(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("a"), "a").unwrap();
+        let plan = Plan {
+            renames: vec![
+                Rename {
+                    from: root.join("a"),
+                    to: root.join("b"),
+                },
+                Rename {
+                    from: root.join("missing"),
+                    to: root.join("c"),
+                },
+            ],
+            rewrites: vec![],
+        };
+
+        assert!(apply_plan(&plan).is_err());
+        assert!(root.join("a").exists());
+        assert!(!root.join("b").exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn undo_without_journal(ctx: &mut Context) {
+        let journal = ctx.root.path().join("journal.toml");
+        assert_eq!(
+            undo(&journal, false).unwrap_err().to_string(),
+            "Nothing to undo"
+        );
+    }
+}
@@ -1,16 +1,17 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use log::{debug, error};
+use log::{debug, error, info, warn};
 
 use regex::Regex;
 
-use super::utils::find_files_with_extension;
-
-static DISC_PATTERN: OnceLock<Regex> = OnceLock::new();
+use super::config::{load_optional_config_recursively, System};
+use super::disc::{find_discs, get_disc_directory, Disc, HIDDEN_DISC_DIRECTORY};
+use super::error::Error;
+use super::frontend::{update_gamelist, update_lpl, Core};
+use super::plan::{apply_plan, get_journal_path, log_plan, Plan, Rename};
+use super::systems::{find_system, Media};
 
 #[derive(Debug, clap::Args)]
 #[command(about = "Create playlist files for multidisc games")]
@@ -20,93 +21,1169 @@ pub struct Args {
     command: Option<Commands>,
 
     #[command(flatten)]
-    generate: GenerateArgs,
+    generate: Option<GenerateArgs>,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Commands {
     #[command(about = "Generate playlist files for multidisc games")]
     Generate(GenerateArgs),
+
+    #[command(about = "Export games to a frontend's playlist")]
+    Export(ExportArgs),
+
+    #[command(about = "Undo the last playlist generation")]
+    Undo(UndoArgs),
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    Gamelist,
+    Lpl,
 }
 
 #[derive(Debug, clap::Args)]
 struct GenerateArgs {
     #[arg(help = "The location to check for files")]
     source: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "The system the games are for, defaults to the source directory's name"
+    )]
+    system: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Where to put the discs, defaults to the layout in retro.toml"
+    )]
+    layout: Option<Layout>,
+
+    #[arg(short, long, help = "Update existing playlists that are out of date")]
+    update: bool,
+
+    #[arg(long, help = "Don't write the playlists")]
+    dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ExportArgs {
+    #[arg(
+        value_enum,
+        help = "The format to export to, an EmulationStation gamelist.xml or a RetroArch playlist"
+    )]
+    format: ExportFormat,
+
+    #[arg(help = "The location the games are linked to")]
+    root: PathBuf,
+
+    #[arg(required_unless_present = "all", help = "System to export")]
+    systems: Vec<String>,
+
+    #[arg(long, conflicts_with = "systems", help = "Export all systems")]
+    all: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Where to write RetroArch playlists, defaults to the root"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "core_name",
+        help = "The path of the RetroArch core to run the games with"
+    )]
+    core_path: Option<String>,
+
+    #[arg(
+        long,
+        requires = "core_path",
+        help = "The name of the RetroArch core to run the games with"
+    )]
+    core_name: Option<String>,
+
+    #[arg(long, help = "Don't write the playlists")]
+    dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct UndoArgs {
+    #[arg(long, help = "Don't move the files")]
+    dry_run: bool,
 }
 
 impl Args {
-    pub fn dispatch(self) -> Result<(), String> {
-        let cmd = self.command.unwrap_or(Commands::Generate(self.generate));
+    pub fn dispatch(self) -> Result<(), Error> {
+        let cmd = self
+            .command
+            .or(self.generate.map(Commands::Generate))
+            .ok_or_else(|| Error::from("No command provided"))?;
         match cmd {
-            Commands::Generate(args) => generate_m3u_playlists(args.source),
+            Commands::Generate(args) => generate_m3u_playlists(args, &get_playlist_journal_path()?),
+            Commands::Export(args) => export_playlists(args),
+            Commands::Undo(args) => undo_playlists(&get_playlist_journal_path()?, args.dry_run),
         }
     }
 }
 
-fn generate_m3u_playlists(source: PathBuf) -> Result<(), String> {
-    debug!("Generating playlists for files in {source:?}");
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub playlist: PlaylistConfig,
+    #[serde(default)]
+    pub systems: HashMap<String, System>,
+}
 
-    let re = DISC_PATTERN.get_or_init(|| {
-        Regex::new(r"(?<before>.+) \(Disc (?<disc>\d+)\)(?<after>.*)\.[^.]+$")
-            .expect("Failed to compile regex pattern")
-    });
+impl Config {
+    // Systems in retro.toml use the same extensions they're linked with, and systems that aren't in
+    // it use the built-in system's extensions. Any other system falls back to CHD files. Playlists
+    // are never discs themselves, so they're left out.
+    pub fn get_extensions(&self, system: &str) -> Vec<String> {
+        let extensions = if let Some(system_config) = self.systems.get(system) {
+            system_config.get_extensions(system)
+        } else if let Some(known) = find_system(system) {
+            known.get_extensions()
+        } else {
+            vec!["chd".to_string()]
+        };
+        extensions
+            .into_iter()
+            .filter(|ext| !ext.eq_ignore_ascii_case("m3u"))
+            .collect()
+    }
+}
 
-    let mut matches: HashMap<String, Vec<String>> = HashMap::new();
+// Flat playlists sit next to their discs. The hidden layout moves the discs into a hidden directory
+// next to the playlist, and the folder layout moves the playlist and its discs into a directory
+// named after the playlist (e.g., `Game.m3u/Game.m3u`), which some frontends show as a single game.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Flat,
+    Hidden,
+    Folder,
+}
 
-    let chd_ext = ["chd"];
-    for file in find_files_with_extension(&source, &chd_ext)? {
-        let file_name = file
-            .file_name()
-            .ok_or_else(|| format!("Failed to get filename for {}", file.display()))?
+// Disc patterns are regular expressions with a `disc` capture group, and they're tried in order.
+// Label patterns match the names some discs have (e.g., `(Arcade Disc)`), which are removed along
+// with the disc so that every disc of a game ends up in the same playlist. Cores are the RetroArch
+// cores exported playlists use for each system (e.g., `[playlist.cores.psx]`).
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PlaylistConfig {
+    pub disc_patterns: Vec<String>,
+    pub label_patterns: Vec<String>,
+    pub layout: Layout,
+    pub cores: HashMap<String, Core>,
+}
+
+impl Default for PlaylistConfig {
+    fn default() -> Self {
+        Self {
+            disc_patterns: vec![
+                r"\((?:Disc|Disk|CD|Side) ?(?<disc>\w+)(?: of \d+)?\)".to_string(),
+                r"\b(?:Disc|Disk|CD) ?(?<disc>\d+)\b".to_string(),
+            ],
+            label_patterns: vec![r"\([^()]+ Dis[ck]\)".to_string()],
+            layout: Layout::Flat,
+            cores: HashMap::new(),
+        }
+    }
+}
+
+impl PlaylistConfig {
+    fn compile(&self) -> Result<(Vec<Regex>, Vec<Regex>), Error> {
+        let disc_patterns = self
+            .disc_patterns
+            .iter()
+            .map(|pattern| {
+                let re = Regex::new(pattern).map_err(|e| {
+                    Error::Config(format!("Invalid disc pattern {}: {}", pattern, e))
+                })?;
+                if !re.capture_names().any(|name| name == Some("disc")) {
+                    return Err(Error::Config(format!(
+                        "Disc pattern {} doesn't have a 'disc' capture group",
+                        pattern
+                    )));
+                }
+                Ok(re)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let label_patterns = self
+            .label_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| Error::Config(format!("Invalid label pattern {}: {}", pattern, e)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok((disc_patterns, label_patterns))
+    }
+}
+
+// Split a file's stem into the name of the playlist it belongs to and its disc. Everything other
+// than the disc and its label is kept in the name so that different regions or revisions of a
+// game get their own playlists.
+fn split_disc(
+    stem: &str,
+    disc_patterns: &[Regex],
+    label_patterns: &[Regex],
+) -> Option<(String, String)> {
+    let (name, disc) = disc_patterns.iter().find_map(|re| {
+        let capture = re.captures(stem)?;
+        let full_match = capture.get(0)?;
+        let disc = capture.name("disc")?.as_str().to_string();
+        let name = format!(
+            "{}{}",
+            &stem[..full_match.start()],
+            &stem[full_match.end()..]
+        );
+        Some((name, disc))
+    })?;
+
+    let name = label_patterns
+        .iter()
+        .fold(name, |name, re| re.replace_all(&name, "").into_owned());
+    let name = name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(['-', '_', ' '])
+        .to_string();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, disc))
+}
+
+// Discs sort by number when they're numbered and alphabetically otherwise.
+fn disc_sort_key(disc: &str) -> (Option<u32>, String) {
+    (disc.parse().ok(), disc.to_lowercase())
+}
+
+// Find every multidisc game below source, keyed by the flat playlist that belongs next to its discs
+// (or next to the disc directory they're in). The discs are in order, and a disc's descriptor
+// (e.g., a cue) is used instead of its tracks.
+pub fn find_playlists(
+    source: &Path,
+    config: &PlaylistConfig,
+    extensions: &[String],
+) -> Result<BTreeMap<PathBuf, Vec<Disc>>, Error> {
+    let (disc_patterns, label_patterns) = config.compile()?;
+
+    let mut playlists: BTreeMap<PathBuf, Vec<(String, Disc)>> = BTreeMap::new();
+
+    let extensions: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
+    for disc in find_discs(source, &extensions)? {
+        let stem = disc
+            .path
+            .file_stem()
+            .ok_or_else(|| format!("Failed to get filename for {}", disc.path.display()))?
             .to_str()
-            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", file.display()))?;
-        let capture = re.captures(file_name);
-        if let Some(capture) = capture {
-            let before = capture
-                .name("before")
-                .ok_or_else(|| format!("Failed to find regex capture 'before' for {}", file_name))?
-                .as_str();
-            let after = capture
-                .name("after")
-                .ok_or_else(|| format!("Failed to find regex capture 'after' for {}", file_name))?
-                .as_str();
-            let full_match = capture
-                .get(0)
-                .ok_or_else(|| format!("Failed to find regex full match for {}", file_name))?
-                .as_str();
-            matches
-                .entry(format!("{before}{after}"))
+            .ok_or_else(|| {
+                format!(
+                    "Failed to convert file name {} to UTF-8",
+                    disc.path.display()
+                )
+            })?;
+        if let Some((name, number)) = split_disc(stem, &disc_patterns, &label_patterns) {
+            let directory = match get_disc_directory(source, &disc.path) {
+                Some(disc_directory) => disc_directory.parent(),
+                None => disc.path.parent(),
+            }
+            .unwrap_or(source);
+            playlists
+                .entry(directory.join(format!("{name}.m3u")))
                 .or_default()
-                .push(full_match.to_string())
+                .push((number, disc));
         }
     }
 
-    for (playlist, files) in &matches {
-        let playlist_file = source.join(playlist).with_extension("m3u");
-        if playlist_file.exists() {
+    Ok(playlists
+        .into_iter()
+        .map(|(playlist, mut discs)| {
+            discs.sort_by_key(|(number, disc)| (disc_sort_key(number), disc.path.clone()));
+            (playlist, discs.into_iter().map(|(_, disc)| disc).collect())
+        })
+        .collect())
+}
+
+// Playlist entries are relative to the playlist so that the playlist keeps working wherever its
+// directory is linked or copied to.
+pub fn format_playlist(playlist: &Path, files: &[PathBuf]) -> Result<String, Error> {
+    let directory = playlist.parent().unwrap_or(Path::new(""));
+    let mut contents = String::new();
+    for file in files {
+        let entry = file.strip_prefix(directory).map_err(|_| {
+            format!(
+                "{} isn't below the playlist {}",
+                file.display(),
+                playlist.display()
+            )
+        })?;
+        let entry = entry
+            .to_str()
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", file.display()))?;
+        contents.push_str(entry);
+        contents.push('\n');
+    }
+    Ok(contents)
+}
+
+// Get where a playlist and its discs go for a layout.
+fn get_layout_paths(playlist: &Path, layout: Layout) -> (PathBuf, PathBuf) {
+    let directory = playlist.parent().unwrap_or(Path::new(""));
+    match layout {
+        Layout::Flat => (playlist.to_path_buf(), directory.to_path_buf()),
+        Layout::Hidden => (
+            playlist.to_path_buf(),
+            directory.join(HIDDEN_DISC_DIRECTORY),
+        ),
+        Layout::Folder => (
+            playlist.join(playlist.file_name().unwrap_or_default()),
+            playlist.to_path_buf(),
+        ),
+    }
+}
+
+// Plan to move a disc, along with any tracks next to it, into a directory.
+fn plan_disc_move(disc: &Disc, directory: &Path, plan: &mut Plan) -> PathBuf {
+    let parent = disc.path.parent();
+    let files = std::iter::once(&disc.path).chain(
+        disc.tracks
+            .iter()
+            .filter(|track| **track != disc.path && track.parent() == parent),
+    );
+    for file in files {
+        let to = directory.join(file.file_name().unwrap_or_default());
+        if to != *file {
+            plan.renames.push(Rename {
+                from: file.clone(),
+                to,
+            });
+        }
+    }
+    directory.join(disc.path.file_name().unwrap_or_default())
+}
+
+// A playlist that was generated or removed, with its contents before and after. Missing contents
+// mean that there was no file.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct PlaylistChange {
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+}
+
+// Everything a single `playlist generate` changed. It's kept apart from the rename journal so that
+// generating playlists doesn't replace the last rename and undoing it also puts the playlists back.
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Journal {
+    #[serde(default)]
+    directories: Vec<PathBuf>,
+    #[serde(default)]
+    moves: Plan,
+    #[serde(default)]
+    playlists: Vec<PlaylistChange>,
+}
+
+fn get_playlist_journal_path() -> Result<PathBuf, Error> {
+    get_journal_path("playlist-journal", "RETRO_PLAYLIST_JOURNAL")
+}
+
+fn generate_m3u_playlists(args: GenerateArgs, journal: &Path) -> Result<(), Error> {
+    let source = args.source;
+    debug!("Generating playlists for files in {source:?}");
+
+    let config: Config = load_optional_config_recursively(&source)?;
+    let system = match args.system {
+        Some(system) => system,
+        None => fs::canonicalize(&source)
+            .map_err(|e| Error::io("Failed to find", &source, e))?
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("Failed to get system name for {}", source.display()))?,
+    };
+    // Only discs and floppies come in sets that need a playlist.
+    if find_system(&system).is_some_and(|known| known.media == Media::Cartridge) {
+        info!("{system} games are cartridges. Skipping.");
+        return Ok(());
+    }
+    let extensions = config.get_extensions(&system);
+    let layout = args.layout.unwrap_or(config.playlist.layout);
+    debug!("Checking for {extensions:?} discs for {system} with the {layout:?} layout");
+
+    // Moving discs is done as a single batch, and the playlists are only written once every disc is
+    // where it belongs. Everything is recorded in the journal so that it can be undone.
+    let mut plan = Plan::default();
+    let mut directories = BTreeSet::new();
+    let mut stale_playlists = Vec::new();
+    let mut playlists = Vec::new();
+    for (flat_playlist, discs) in find_playlists(&source, &config.playlist, &extensions)? {
+        let (playlist_file, disc_directory) = get_layout_paths(&flat_playlist, layout);
+
+        let mut game_plan = Plan::default();
+        let files: Vec<PathBuf> = discs
+            .iter()
+            .map(|disc| plan_disc_move(disc, &disc_directory, &mut game_plan))
+            .collect();
+        let contents = format_playlist(&playlist_file, &files)?;
+
+        // A playlist from a different layout is in the way of this one.
+        let stale_playlist = match layout {
+            Layout::Folder => flat_playlist.is_file().then(|| flat_playlist.clone()),
+            _ => flat_playlist
+                .is_dir()
+                .then(|| flat_playlist.join(flat_playlist.file_name().unwrap_or_default())),
+        };
+
+        if playlist_file.exists() || stale_playlist.is_some() {
+            if !args.update {
+                warn!("{} exists. Skipping.", flat_playlist.display());
+                continue;
+            }
+            if game_plan.is_empty() && stale_playlist.is_none() {
+                let existing = fs::read_to_string(&playlist_file)
+                    .map_err(|e| Error::io("Failed to read playlist", &playlist_file, e))?;
+                if existing == contents {
+                    debug!("{} is up to date", playlist_file.display());
+                    continue;
+                }
+            }
+        }
+
+        if !game_plan.is_empty() {
+            directories.insert(disc_directory);
+        }
+        plan.extend(game_plan);
+        if let Some(stale_playlist) = stale_playlist {
+            stale_playlists.push((stale_playlist, flat_playlist));
+        }
+        playlists.push((playlist_file, contents));
+    }
+
+    // Nothing is removed or created unless every disc can be moved.
+    plan.check()?;
+
+    if args.dry_run {
+        for (stale_playlist, _) in &stale_playlists {
+            error!("Would remove {stale_playlist:?}");
+        }
+        log_plan(&plan, true);
+        for (playlist_file, _) in &playlists {
+            error!("Would generate {playlist_file:?}");
+        }
+        return Ok(());
+    }
+
+    let mut changes = Journal::default();
+    for (stale_playlist, _) in &stale_playlists {
+        error!("Removing {stale_playlist:?}");
+        let before = read_optional_file(stale_playlist)?;
+        fs::remove_file(stale_playlist)
+            .map_err(|e| Error::io("Failed to remove playlist", stale_playlist, e))?;
+        changes.playlists.push(PlaylistChange {
+            path: stale_playlist.clone(),
+            before,
+            after: None,
+        });
+    }
+    for directory in directories {
+        if directory.is_dir() {
             continue;
         }
+        fs::create_dir_all(&directory)
+            .map_err(|e| Error::io("Failed to create directory", &directory, e))?;
+        changes.directories.push(directory);
+    }
+    if !plan.is_empty() {
+        apply_plan(&plan)?;
+        log_plan(&plan, false);
+    }
+    changes.moves = plan;
+    // Folders from the folder layout are empty once their discs have been moved out.
+    for (_, flat_playlist) in &stale_playlists {
+        if flat_playlist.is_dir() {
+            fs::remove_dir(flat_playlist)
+                .map_err(|e| Error::io("Failed to remove directory", flat_playlist, e))?;
+        }
+    }
 
+    for (playlist_file, contents) in playlists {
         error!("Generating {playlist_file:?}");
 
-        let mut f = File::create(&playlist_file).map_err(|e| {
-            format!(
-                "Failed to create playlist {}: {}",
-                playlist_file.display(),
-                e
+        let before = read_optional_file(&playlist_file)?;
+        fs::write(&playlist_file, &contents)
+            .map_err(|e| Error::io("Failed to write to playlist", &playlist_file, e))?;
+        changes.playlists.push(PlaylistChange {
+            path: playlist_file,
+            before,
+            after: Some(contents),
+        });
+    }
+
+    if changes.playlists.is_empty() {
+        return Ok(());
+    }
+    debug!("Recording playlists in {journal:?}");
+    confy::store_path(journal, &changes).map_err(|e| {
+        Error::Other(format!(
+            "Failed to write journal {}: {}",
+            journal.display(),
+            e
+        ))
+    })
+}
+
+// Revert the last `playlist generate`: the generated playlists are removed, the discs are moved back
+// where they were, and any playlists it replaced are restored.
+fn undo_playlists(journal: &Path, dry_run: bool) -> Result<(), Error> {
+    if !journal.is_file() {
+        return Err("Nothing to undo".into());
+    }
+    let changes: Journal = confy::load_path(journal).map_err(|e| {
+        Error::Other(format!(
+            "Failed to read journal {}: {}",
+            journal.display(),
+            e
+        ))
+    })?;
+    let inverse = changes.moves.inverse();
+
+    inverse.check()?;
+    for change in &changes.playlists {
+        if change.after.is_some() && read_optional_file(&change.path)? != change.after {
+            return Err(format!(
+                "{} has changed since it was generated",
+                change.path.display()
             )
-        })?;
-        for file in files {
-            f.write_all(file.as_bytes()).map_err(|e| {
-                format!(
-                    "Failed to write to playlist {}: {}",
-                    playlist_file.display(),
-                    e
-                )
-            })?;
+            .into());
+        }
+    }
+
+    if dry_run {
+        for change in changes
+            .playlists
+            .iter()
+            .filter(|change| change.before.is_none())
+        {
+            error!("Would remove {:?}", change.path);
+        }
+        log_plan(&inverse, true);
+        for change in changes
+            .playlists
+            .iter()
+            .filter(|change| change.before.is_some())
+        {
+            error!("Would restore {:?}", change.path);
+        }
+        return Ok(());
+    }
+
+    // Generated playlists can be in the way of the discs, e.g., when switching from the folder
+    // layout, so they go first.
+    for change in changes
+        .playlists
+        .iter()
+        .filter(|change| change.before.is_none())
+    {
+        error!("Removing {:?}", change.path);
+        fs::remove_file(&change.path)
+            .map_err(|e| Error::io("Failed to remove playlist", &change.path, e))?;
+    }
+    for directory in inverse
+        .renames
+        .iter()
+        .filter_map(|rename| rename.to.parent())
+    {
+        fs::create_dir_all(directory)
+            .map_err(|e| Error::io("Failed to create directory", directory, e))?;
+    }
+    if !inverse.is_empty() {
+        apply_plan(&inverse)?;
+        log_plan(&inverse, false);
+    }
+    for directory in changes.directories.iter().rev() {
+        if let Err(e) = fs::remove_dir(directory) {
+            warn!("Failed to remove directory {}: {}", directory.display(), e);
+        }
+    }
+    for change in &changes.playlists {
+        let Some(before) = &change.before else {
+            continue;
+        };
+        error!("Restoring {:?}", change.path);
+        if let Some(directory) = change.path.parent() {
+            fs::create_dir_all(directory)
+                .map_err(|e| Error::io("Failed to create directory", directory, e))?;
+        }
+        fs::write(&change.path, before)
+            .map_err(|e| Error::io("Failed to write to playlist", &change.path, e))?;
+    }
+
+    fs::remove_file(journal).map_err(|e| Error::io("Failed to remove journal", journal, e))
+}
+
+fn read_optional_file(path: &Path) -> Result<Option<String>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(path)
+        .map(Some)
+        .map_err(|e| Error::io("Failed to read", path, e))
+}
+
+fn read_playlist(playlist: &Path) -> Result<Vec<PathBuf>, Error> {
+    let directory = playlist.parent().unwrap_or(Path::new(""));
+    Ok(read_optional_file(playlist)?
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| directory.join(line))
+        .collect())
+}
+
+// Find the games in a directory that a frontend should list. Multidisc games are only listed once,
+// through their playlist, whichever layout they use.
+fn find_games(directory: &Path, extensions: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut extensions: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
+    extensions.push("m3u");
+    let discs = find_discs(directory, &extensions)?;
+
+    let mut in_playlists = HashSet::new();
+    for disc in &discs {
+        if disc.path.extension().is_some_and(|ext| ext == "m3u") {
+            in_playlists.extend(read_playlist(&disc.path)?);
+        }
+    }
+
+    let mut games = BTreeSet::new();
+    for disc in discs {
+        match get_disc_directory(directory, &disc.path) {
+            Some(disc_directory) => {
+                if disc_directory.extension().is_some_and(|ext| ext == "m3u") {
+                    games.insert(disc_directory.to_path_buf());
+                }
+            }
+            None => {
+                if !in_playlists.contains(&disc.path) {
+                    games.insert(disc.path);
+                }
+            }
+        }
+    }
+    Ok(games.into_iter().collect())
+}
+
+fn export_playlists(args: ExportArgs) -> Result<(), Error> {
+    let root =
+        std::path::absolute(&args.root).map_err(|e| Error::io("Failed to find", &args.root, e))?;
+    debug!(
+        "Exporting {:?} playlists for games in {root:?}",
+        args.format
+    );
+
+    let config: Config = load_optional_config_recursively(&root)?;
+    let systems = if args.all {
+        let mut systems: Vec<String> = config.systems.keys().cloned().collect();
+        systems.sort();
+        systems
+    } else {
+        args.systems
+    };
+    if systems.is_empty() {
+        info!("No systems found in config. Skipping.");
+    }
+    let output = args.output.unwrap_or_else(|| root.clone());
+    let core = match (args.core_path, args.core_name) {
+        (Some(path), Some(name)) => Some(Core { path, name }),
+        _ => None,
+    };
+
+    for system in &systems {
+        let extensions = config.get_extensions(system);
+        let system_config = config.systems.get(system);
+        let destinations = system_config.map_or_else(
+            || vec![system.clone()],
+            |system_config| system_config.get_destinations(system),
+        );
+
+        for destination in destinations {
+            let mut directory = root.join(&destination);
+            if let Some(extra_path) = system_config.and_then(|config| config.extra_path.as_ref()) {
+                directory = directory.join(extra_path);
+            }
+            if !directory.is_dir() {
+                info!("{} does not exist. Skipping.", directory.display());
+                continue;
+            }
+
+            let games = find_games(&directory, &extensions)?;
+            let file = match args.format {
+                ExportFormat::Gamelist => directory.join("gamelist.xml"),
+                ExportFormat::Lpl => {
+                    let name = Path::new(&destination)
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or(system);
+                    output.join(format!("{name}.lpl"))
+                }
+            };
+            let existing = read_optional_file(&file)?;
+            let contents = match args.format {
+                ExportFormat::Gamelist => update_gamelist(existing.as_deref(), &directory, &games)?,
+                ExportFormat::Lpl => {
+                    let db_name = file
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or_default();
+                    let core = core.as_ref().or(config.playlist.cores.get(system));
+                    update_lpl(existing.as_deref(), &games, db_name, core)?
+                }
+            };
+
+            if existing.as_ref() == Some(&contents) {
+                debug!("{} is up to date", file.display());
+                continue;
+            }
+            if args.dry_run {
+                error!("Would export {file:?}");
+                continue;
+            }
+
+            error!("Exporting {file:?}");
+            fs::write(&file, contents).map_err(|e| Error::io("Failed to write to", &file, e))?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Context {
+            Context {
+                root: TempDir::new("retro").unwrap(),
+            }
+        }
+    }
+
+    fn find_playlist_paths(root: &Path, extensions: &[&str]) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+        let extensions: Vec<String> = extensions.iter().map(|ext| ext.to_string()).collect();
+        find_playlists(root, &PlaylistConfig::default(), &extensions)
+            .unwrap()
+            .into_iter()
+            .map(|(playlist, discs)| (playlist, discs.into_iter().map(|disc| disc.path).collect()))
+            .collect()
+    }
+
+    fn split(stem: &str) -> Option<(String, String)> {
+        let (disc_patterns, label_patterns) = PlaylistConfig::default().compile().unwrap();
+        split_disc(stem, &disc_patterns, &label_patterns)
+    }
+
+    #[test]
+    fn split_disc_with_default_patterns() {
+        let game = |disc: &str| Some(("Game (USA)".to_string(), disc.to_string()));
+        assert_eq!(split("Game (USA) (Disc 1)"), game("1"));
+        assert_eq!(split("Game (USA) (Disc A)"), game("A"));
+        assert_eq!(split("Game (USA) (Disc 2 of 3)"), game("2"));
+        assert_eq!(split("Game (USA) (Side B)"), game("B"));
+        assert_eq!(split("Game (USA) (CD1)"), game("1"));
+        assert_eq!(split("Game (USA) Disk 2"), game("2"));
+        assert_eq!(split("Game (USA) - CD2"), game("2"));
+    }
+
+    #[test]
+    fn split_disc_removes_disc_labels() {
+        assert_eq!(
+            split("Game (Japan) (Disc 2) (Arcade Disc)"),
+            Some(("Game (Japan)".to_string(), "2".to_string()))
+        );
+    }
+
+    #[test]
+    fn split_disc_keeps_regions_and_revisions() {
+        assert_eq!(
+            split("Game (Europe) (Disc 1) (Rev 1)"),
+            Some(("Game (Europe) (Rev 1)".to_string(), "1".to_string()))
+        );
+        assert_eq!(
+            split("Game (USA) (Disc 1)"),
+            Some(("Game (USA)".to_string(), "1".to_string()))
+        );
+    }
+
+    #[test]
+    fn split_disc_without_disc() {
+        assert_eq!(split("Game (USA)"), None);
+        assert_eq!(split("Discworld (USA)"), None);
+    }
+
+    #[test]
+    fn playlist_config_requires_disc_capture_group() {
+        let config = PlaylistConfig {
+            disc_patterns: vec![r"\(Disc \d+\)".to_string()],
+            ..Default::default()
+        };
+        assert!(config.compile().is_err());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_playlists_sorts_discs(ctx: &mut Context) {
+        let root = ctx.root.path();
+        for disc in ["10", "2", "1"] {
+            write(root.join(format!("Game (USA) (Disc {disc}).chd")), "").unwrap();
+        }
+
+        let playlists = find_playlist_paths(root, &["chd"]);
+        assert_eq!(
+            playlists,
+            BTreeMap::from([(
+                root.join("Game (USA).m3u"),
+                vec![
+                    root.join("Game (USA) (Disc 1).chd"),
+                    root.join("Game (USA) (Disc 2).chd"),
+                    root.join("Game (USA) (Disc 10).chd"),
+                ]
+            )])
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_playlists_keeps_directories_apart(ctx: &mut Context) {
+        let root = ctx.root.path();
+        for system in ["psx", "saturn"] {
+            create_dir(root.join(system)).unwrap();
+            write(root.join(system).join("Game (Disc 1).chd"), "").unwrap();
+            write(root.join(system).join("Game (Disc 2).chd"), "").unwrap();
+        }
+
+        let playlists = find_playlist_paths(root, &["chd"]);
+        assert_eq!(
+            playlists.keys().collect::<Vec<_>>(),
+            vec![
+                &root.join("psx").join("Game.m3u"),
+                &root.join("saturn").join("Game.m3u"),
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_playlists_prefers_descriptors(ctx: &mut Context) {
+        let root = ctx.root.path();
+        for disc in ["1", "2"] {
+            let name = format!("Game (Disc {disc})");
+            write(
+                root.join(format!("{name}.cue")),
+                format!("FILE \"{name}.bin\" BINARY\n  TRACK 01 MODE2/2352\n"),
+            )
+            .unwrap();
+            write(root.join(format!("{name}.bin")), "").unwrap();
+        }
+
+        let playlists = find_playlist_paths(root, &["cue", "bin"]);
+        assert_eq!(
+            playlists,
+            BTreeMap::from([(
+                root.join("Game.m3u"),
+                vec![
+                    root.join("Game (Disc 1).cue"),
+                    root.join("Game (Disc 2).cue")
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn config_get_extensions() {
+        let config = Config {
+            systems: HashMap::from([(
+                "psx".to_string(),
+                System {
+                    extensions: Some(vec!["chd".to_string(), "m3u".to_string()]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(config.get_extensions("psx"), vec!["chd"]);
+        assert_eq!(config.get_extensions("amiga"), vec!["adf"]);
+        assert_eq!(config.get_extensions("unknown"), vec!["chd"]);
+    }
+
+    #[test]
+    fn format_playlist_uses_relative_paths() {
+        let contents = format_playlist(
+            Path::new("/games/Game.m3u"),
+            &[
+                PathBuf::from("/games/Game (Disc A).chd"),
+                PathBuf::from("/games/discs/Game (Disc B).chd"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(contents, "Game (Disc A).chd\ndiscs/Game (Disc B).chd\n");
+    }
+
+    fn generate_args(root: &Path, layout: Layout, update: bool) -> GenerateArgs {
+        GenerateArgs {
+            source: root.to_path_buf(),
+            system: None,
+            layout: Some(layout),
+            update,
+            dry_run: false,
+        }
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_m3u_playlists_updates_existing_playlists(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        write(root.join("Game.m3u"), "Game (Disc 1).chd\n").unwrap();
+
+        generate_m3u_playlists(generate_args(root, Layout::Flat, false), &journal).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Game.m3u")).unwrap(),
+            "Game (Disc 1).chd\n"
+        );
+        generate_m3u_playlists(generate_args(root, Layout::Flat, true), &journal).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Game.m3u")).unwrap(),
+            "Game (Disc 1).chd\nGame (Disc 2).chd\n"
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_m3u_playlists_with_hidden_layout(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(
+            root.join("Game (Disc 1).cue"),
+            "FILE \"Game (Disc 1).bin\" BINARY\n",
+        )
+        .unwrap();
+        write(root.join("Game (Disc 1).bin"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+
+        let mut args = generate_args(root, Layout::Hidden, false);
+        args.system = Some("psx".to_string());
+        generate_m3u_playlists(args, &journal).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Game.m3u")).unwrap(),
+            ".discs/Game (Disc 1).cue\n.discs/Game (Disc 2).chd\n"
+        );
+        for file in [
+            "Game (Disc 1).cue",
+            "Game (Disc 1).bin",
+            "Game (Disc 2).chd",
+        ] {
+            assert!(root.join(".discs").join(file).is_file());
+            assert!(!root.join(file).exists());
+        }
+        assert!(journal.is_file());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_m3u_playlists_switches_from_folder_to_flat_layout(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+
+        generate_m3u_playlists(generate_args(root, Layout::Folder, false), &journal).unwrap();
+        let folder = root.join("Game.m3u");
+        assert_eq!(
+            fs::read_to_string(folder.join("Game.m3u")).unwrap(),
+            "Game (Disc 1).chd\nGame (Disc 2).chd\n"
+        );
+        assert!(folder.join("Game (Disc 1).chd").is_file());
+
+        generate_m3u_playlists(generate_args(root, Layout::Flat, false), &journal).unwrap();
+        assert!(folder.is_dir());
+
+        generate_m3u_playlists(generate_args(root, Layout::Flat, true), &journal).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Game.m3u")).unwrap(),
+            "Game (Disc 1).chd\nGame (Disc 2).chd\n"
+        );
+        assert!(root.join("Game (Disc 2).chd").is_file());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_m3u_playlists_skips_cartridge_systems(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).gba"), "").unwrap();
+        write(root.join("Game (Disc 2).gba"), "").unwrap();
+
+        let mut args = generate_args(root, Layout::Flat, false);
+        args.system = Some("gba".to_string());
+        generate_m3u_playlists(args, &journal).unwrap();
+        assert!(!root.join("Game.m3u").exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn undo_playlists_moves_discs_back(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        generate_m3u_playlists(generate_args(root, Layout::Hidden, false), &journal).unwrap();
+        assert!(root.join(".discs").join("Game (Disc 1).chd").is_file());
+
+        undo_playlists(&journal, false).unwrap();
+        assert!(root.join("Game (Disc 1).chd").is_file());
+        assert!(root.join("Game (Disc 2).chd").is_file());
+        assert!(!root.join("Game.m3u").exists());
+        assert!(!root.join(".discs").exists());
+        assert!(!journal.exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn undo_playlists_restores_replaced_layout(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        generate_m3u_playlists(generate_args(root, Layout::Folder, false), &journal).unwrap();
+        fs::remove_file(&journal).unwrap();
+        generate_m3u_playlists(generate_args(root, Layout::Flat, true), &journal).unwrap();
+        assert!(root.join("Game.m3u").is_file());
+
+        undo_playlists(&journal, false).unwrap();
+        let folder = root.join("Game.m3u");
+        assert_eq!(
+            fs::read_to_string(folder.join("Game.m3u")).unwrap(),
+            "Game (Disc 1).chd\nGame (Disc 2).chd\n"
+        );
+        assert!(folder.join("Game (Disc 1).chd").is_file());
+        assert!(!root.join("Game (Disc 1).chd").exists());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn undo_playlists_refuses_changed_playlists(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        generate_m3u_playlists(generate_args(root, Layout::Flat, false), &journal).unwrap();
+        write(root.join("Game.m3u"), "Game (Disc 1).chd\n").unwrap();
+
+        assert!(undo_playlists(&journal, false).is_err());
+        assert!(root.join("Game.m3u").is_file());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn generate_m3u_playlists_checks_moves_before_removing_playlists(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let journal = root.join("journal");
+        write(root.join("Game (Disc 1).chd"), "").unwrap();
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        generate_m3u_playlists(generate_args(root, Layout::Folder, false), &journal).unwrap();
+
+        // A disc that's in the way of moving the discs back out of the folder.
+        write(root.join("Game (Disc 2).chd"), "").unwrap();
+        assert!(generate_m3u_playlists(generate_args(root, Layout::Flat, true), &journal).is_err());
+        assert!(root.join("Game.m3u").join("Game.m3u").is_file());
+        assert!(root.join("Game.m3u").join("Game (Disc 1).chd").is_file());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn find_games_lists_multidisc_games_once(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(root.join("Flat (Disc 1).chd"), "").unwrap();
+        write(root.join("Flat (Disc 2).chd"), "").unwrap();
+        write(
+            root.join("Flat.m3u"),
+            "Flat (Disc 1).chd\nFlat (Disc 2).chd\n",
+        )
+        .unwrap();
+        create_dir(root.join(".discs")).unwrap();
+        write(root.join(".discs").join("Hidden (Disc 1).chd"), "").unwrap();
+        write(root.join("Hidden.m3u"), ".discs/Hidden (Disc 1).chd\n").unwrap();
+        create_dir(root.join("Folder.m3u")).unwrap();
+        write(root.join("Folder.m3u").join("Folder (Disc 1).chd"), "").unwrap();
+        write(
+            root.join("Folder.m3u").join("Folder.m3u"),
+            "Folder (Disc 1).chd\n",
+        )
+        .unwrap();
+        write(root.join("Single.chd"), "").unwrap();
+
+        assert_eq!(
+            find_games(root, &["chd".to_string()]).unwrap(),
+            vec![
+                root.join("Flat.m3u"),
+                root.join("Folder.m3u"),
+                root.join("Hidden.m3u"),
+                root.join("Single.chd"),
+            ]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn export_playlists_writes_gamelist_for_each_destination(ctx: &mut Context) {
+        let root = ctx.root.path();
+        write(
+            root.join("retro.toml"),
+            "[systems.psx]\ndumper = \"redump\"\ndestinations = [\"psx\", \"ps1\"]\nextension = \"chd\"\n",
+        )
+        .unwrap();
+        create_dir(root.join("psx")).unwrap();
+        write(root.join("psx").join("Game, The (USA).chd"), "").unwrap();
+
+        export_playlists(ExportArgs {
+            format: ExportFormat::Gamelist,
+            root: root.to_path_buf(),
+            systems: vec!["psx".to_string()],
+            all: false,
+            output: None,
+            core_path: None,
+            core_name: None,
+            dry_run: false,
+        })
+        .unwrap();
+        let gamelist = fs::read_to_string(root.join("psx").join("gamelist.xml")).unwrap();
+        assert!(gamelist.contains("<path>./Game, The (USA).chd</path>"));
+        assert!(gamelist.contains("<name>The Game</name>"));
+        assert!(!root.join("ps1").exists());
+    }
+}
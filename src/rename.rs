@@ -1,10 +1,19 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use log::{debug, error};
+use log::{debug, error, warn};
 
-use super::utils::{find_files_with_extension, longest_common_prefix};
+use regex::Regex;
+
+use super::cue::CueSheet;
+use super::error::Error;
+use super::name::{convert_name, load_name_config, parse_title, FIELDS};
+use super::plan::{get_journal_path, run_plan, undo, Plan, Rename, Rewrite};
+use super::utils::{find_files, find_files_with_extension, longest_common_prefix};
+
+static FIELD_PATTERN: OnceLock<Regex> = OnceLock::new();
 
 #[derive(Debug, clap::Args)]
 #[command(about = "Rename files")]
@@ -14,13 +23,22 @@ pub struct Args {
     command: Option<Commands>,
 
     #[command(flatten)]
-    bin_cue: BinCueArgs,
+    bin_cue: Option<BinCueArgs>,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Commands {
     #[command(about = "Rename bin/cue files")]
     BinCue(BinCueArgs),
+
+    #[command(about = "Rename GoodTools and TOSEC named files to No-Intro names")]
+    NoIntro(NoIntroArgs),
+
+    #[command(about = "Rename files using a regular expression")]
+    Pattern(PatternArgs),
+
+    #[command(about = "Undo the last rename")]
+    Undo(UndoArgs),
 }
 
 #[derive(Debug, clap::Args)]
@@ -30,90 +48,600 @@ struct BinCueArgs {
 
     #[arg(help = "The new prefix to use, defaults to the source directory's name")]
     new: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "new",
+        help = "Rename every game below the source directory, using each game's directory name"
+    )]
+    recursive: bool,
+
+    #[arg(long, help = "Don't rename the files")]
+    dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct NoIntroArgs {
+    #[arg(help = "The location to check for files")]
+    source: PathBuf,
+
+    #[arg(
+        short,
+        long = "extension",
+        help = "Only rename files with this extension"
+    )]
+    extensions: Vec<String>,
+
+    #[arg(long, help = "Don't rename the files")]
+    dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct PatternArgs {
+    #[arg(help = "The location to check for files")]
+    source: PathBuf,
+
+    #[arg(help = "The regular expression to search file names for")]
+    search: String,
+
+    #[arg(
+        help = "The replacement, which can use capture groups (e.g., ${1}) and fields parsed from the file name (e.g., {title}, {region}, {languages}, {disc}, {tags}, or {ext:lower})"
+    )]
+    replace: String,
+
+    #[arg(
+        short,
+        long = "extension",
+        help = "Only rename files with this extension"
+    )]
+    extensions: Vec<String>,
+
+    #[arg(long, help = "Don't rename the files")]
+    dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct UndoArgs {
+    #[arg(long, help = "Don't rename the files")]
+    dry_run: bool,
 }
 
 impl Args {
-    pub fn dispatch(self) -> Result<(), String> {
-        let cmd = self.command.unwrap_or(Commands::BinCue(self.bin_cue));
+    pub fn dispatch(self) -> Result<(), Error> {
+        let cmd = self
+            .command
+            .or(self.bin_cue.map(Commands::BinCue))
+            .ok_or_else(|| Error::from("No command provided"))?;
         match cmd {
-            Commands::BinCue(args) => rename_bin_cue_files(args.source, args.new),
+            Commands::BinCue(args) => {
+                let plan = if args.recursive {
+                    plan_recursive_bin_cue_rename(&args.source)?
+                } else {
+                    plan_bin_cue_rename(&args.source, args.new)?
+                };
+                run_plan(&plan, &get_rename_journal_path()?, args.dry_run)
+            }
+            Commands::NoIntro(args) => {
+                let plan = plan_no_intro_rename(&args.source, &args.extensions)?;
+                run_plan(&plan, &get_rename_journal_path()?, args.dry_run)
+            }
+            Commands::Pattern(args) => {
+                let plan = plan_pattern_rename(
+                    &args.source,
+                    &args.search,
+                    &args.replace,
+                    &args.extensions,
+                )?;
+                run_plan(&plan, &get_rename_journal_path()?, args.dry_run)
+            }
+            Commands::Undo(args) => undo(&get_rename_journal_path()?, args.dry_run),
+        }
+    }
+}
+
+fn get_rename_journal_path() -> Result<PathBuf, Error> {
+    get_journal_path("journal", "RETRO_JOURNAL")
+}
+
+// Rewrite the FILE lines of every cue sheet affected by a plan to use the new names of the files
+// they reference. This includes cue sheets that are renamed and any cue sheets that sit next to a
+// renamed file.
+fn plan_cue_rewrites(plan: &mut Plan) -> Result<(), Error> {
+    let new_names: HashMap<&PathBuf, &PathBuf> = plan
+        .renames
+        .iter()
+        .map(|rename| (&rename.from, &rename.to))
+        .collect();
+
+    let mut cue_sheets: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    let mut directories = HashSet::new();
+    for rename in &plan.renames {
+        if rename.from.extension().is_some_and(|ext| ext == "cue") {
+            cue_sheets.insert(rename.from.clone(), rename.to.clone());
+        }
+        directories.insert(rename.from.parent().unwrap_or(Path::new("")));
+    }
+    for directory in directories {
+        for cue_sheet in find_files_with_extension(directory, &["cue"])? {
+            if cue_sheet.parent() == Some(directory) {
+                cue_sheets.entry(cue_sheet.clone()).or_insert(cue_sheet);
+            }
+        }
+    }
+
+    let mut rewrites = Vec::new();
+    for (from, to) in cue_sheets {
+        let contents = fs::read_to_string(&from)
+            .map_err(|e| Error::io("Failed to read cue file", &from, e))?;
+        let sheet: CueSheet = contents
+            .parse()
+            .map_err(|e| Error::Parse(format!("Failed to parse {}: {}", from.display(), e)))?;
+
+        let directory = from.parent().unwrap_or(Path::new(""));
+        let mut names = Vec::new();
+        for file in &sheet.files {
+            let name = match new_names.get(&directory.join(&file.name)) {
+                Some(new_path) => new_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| {
+                        format!(
+                            "Failed to convert file name {} to UTF-8",
+                            new_path.display()
+                        )
+                    })?
+                    .to_string(),
+                None => file.name.clone(),
+            };
+            names.push(name);
+        }
+
+        let after = sheet.rewrite_file_names(&contents, &names)?;
+        if after != contents {
+            rewrites.push(Rewrite {
+                path: to,
+                before: contents,
+                after,
+            });
         }
     }
+
+    plan.rewrites.extend(rewrites);
+    Ok(())
 }
 
-fn rename_bin_cue_files(source: PathBuf, replacement_root: Option<String>) -> Result<(), String> {
+fn plan_bin_cue_rename(source: &Path, replacement_root: Option<String>) -> Result<Plan, Error> {
+    let source = fs::canonicalize(source).map_err(|e| Error::io("Failed to find", source, e))?;
     let new_prefix = match replacement_root {
         Some(replacement_root) => replacement_root,
-        None => {
-            let source_str = source.to_str().ok_or_else(|| {
+        None => source
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
                 format!(
                     "Failed to convert source path {} to UTF-8",
                     source.display()
                 )
-            })?;
-            // Remove trailing slash if present
-            source_str
-                .strip_suffix("/")
-                .unwrap_or(source_str)
-                .to_string()
-        }
+            })?
+            .to_string(),
     };
     debug!("Renaming all bin and cue files in \"{source:?}\" to start with \"{new_prefix}\"");
 
-    let mut file_names = Vec::new();
     let bin_cue_ext = ["bin", "cue"];
-    for file in find_files_with_extension(&source, &bin_cue_ext)? {
-        if let Some(file_name) = file.file_name() {
-            let file_name_str = file_name.to_str().ok_or_else(|| {
-                format!("Failed to convert file name {} to UTF-8", file.display())
-            })?;
-            file_names.push(file_name_str.to_string());
-        }
+    let mut files = find_files_with_extension(&source, &bin_cue_ext)?;
+    files.sort();
+
+    // The prefix is found using only the stems so that it can never eat into the extension.
+    let mut stems = Vec::new();
+    for file in &files {
+        let stem = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", file.display()))?;
+        stems.push(stem.to_string());
     }
 
-    let common = longest_common_prefix(&file_names);
+    let common = longest_common_prefix(&stems);
     if common.is_empty() {
-        return Err("Failed to find common prefix".to_string());
+        return Err("Failed to find common prefix".into());
     }
 
-    for file_name in &file_names {
-        let old_path = source.join(file_name);
-        let new_file_name = file_name.replace(&common, &new_prefix);
-        let new_path = source.join(&new_file_name);
+    let mut plan = Plan::default();
+    for file in &files {
+        let file_name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", file.display()))?;
+        let new_file_name = format!("{}{}", new_prefix, &file_name[common.len()..]);
+        if new_file_name != file_name {
+            plan.renames.push(Rename {
+                from: file.clone(),
+                to: file.with_file_name(new_file_name),
+            });
+        }
+    }
 
-        if let Err(e) = fs::rename(&old_path, &new_path) {
-            error!(
-                "Failed to rename {} to {}: {}",
-                old_path.display(),
-                new_path.display(),
-                e
-            );
+    plan_cue_rewrites(&mut plan)?;
+    Ok(plan)
+}
+
+// Expand `{field}` placeholders in a replacement using the parts of the file's name. A field can be
+// followed by a filter, e.g., `{ext:lower}`. Fields that aren't in the name expand to nothing.
+fn expand_template(template: &str, stem: &str, extension: &str) -> Result<String, Error> {
+    let re = FIELD_PATTERN.get_or_init(|| {
+        Regex::new(r"\{(?<field>\w+)(?::(?<filter>\w+))?\}")
+            .expect("Failed to compile regex pattern")
+    });
+    let title = parse_title(stem);
+
+    let mut expanded = String::new();
+    let mut last = 0;
+    for capture in re.captures_iter(template) {
+        let full_match = capture
+            .get(0)
+            .ok_or_else(|| format!("Failed to find regex full match for {}", template))?;
+        // Leave capture group references like `${1}` for the regex replacement.
+        if template[..full_match.start()].ends_with('$') {
             continue;
         }
+        let field = capture.name("field").map_or("", |m| m.as_str());
+        let value = match field {
+            "ext" => extension.to_string(),
+            "stem" => stem.to_string(),
+            _ if FIELDS.contains(&field) => title.get_field(field).unwrap_or_default(),
+            _ => return Err(format!("Unknown field '{}'", field).into()),
+        };
+        let value = match capture.name("filter").map(|m| m.as_str()) {
+            None => value,
+            Some("lower") => value.to_lowercase(),
+            Some("upper") => value.to_uppercase(),
+            Some(filter) => return Err(format!("Unknown filter '{}'", filter).into()),
+        };
 
-        if let Some(ext) = new_path.extension() {
-            if ext == "cue" {
-                let contents = fs::read_to_string(&new_path).map_err(|e| {
-                    format!("Failed to read cue file {}: {}", new_path.display(), e)
-                })?;
-                let new = contents.replace(&common, &new_prefix);
-                let mut file = fs::OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .open(&new_path)
-                    .map_err(|e| {
-                        format!(
-                            "Failed to open cue file {} for writing: {}",
-                            new_path.display(),
-                            e
-                        )
-                    })?;
-                file.write_all(new.as_bytes()).map_err(|e| {
-                    format!("Failed to write to cue file {}: {}", new_path.display(), e)
-                })?;
+        expanded.push_str(&template[last..full_match.start()]);
+        // The expanded template is used as a regex replacement, so literal dollar signs need to be
+        // escaped.
+        expanded.push_str(&value.replace('$', "$$"));
+        last = full_match.end();
+    }
+    expanded.push_str(&template[last..]);
+
+    Ok(expanded)
+}
+
+// Plan to rename every file below source (optionally only those with one of the extensions) to the
+// name returned by `rename`, which is given the file's name, stem, and extension.
+fn plan_file_renames<F>(source: &Path, extensions: &[String], rename: F) -> Result<Plan, Error>
+where
+    F: Fn(&str, &str, &str) -> Result<Option<String>, Error>,
+{
+    let source = fs::canonicalize(source).map_err(|e| Error::io("Failed to find", source, e))?;
+
+    let mut files = if extensions.is_empty() {
+        find_files(&source)?
+    } else {
+        let extensions: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
+        find_files_with_extension(&source, &extensions)?
+    };
+    files.sort();
+
+    let mut plan = Plan::default();
+    for file in &files {
+        let file_name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Failed to convert file name {} to UTF-8", file.display()))?;
+        let stem = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        let extension = file.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let Some(new_file_name) = rename(file_name, stem, extension)? else {
+            continue;
+        };
+        if new_file_name.is_empty() || new_file_name.contains('/') {
+            return Err(format!(
+                "{} can't be renamed to \"{}\"",
+                file.display(),
+                new_file_name
+            )
+            .into());
+        }
+        if new_file_name != file_name {
+            plan.renames.push(Rename {
+                from: file.clone(),
+                to: file.with_file_name(new_file_name),
+            });
+        }
+    }
+
+    plan_cue_rewrites(&mut plan)?;
+    Ok(plan)
+}
+
+fn plan_pattern_rename(
+    source: &Path,
+    search: &str,
+    template: &str,
+    extensions: &[String],
+) -> Result<Plan, Error> {
+    let re = Regex::new(search).map_err(|e| format!("Invalid search pattern: {}", e))?;
+    debug!("Renaming files in {source:?} matching {search:?} to {template:?}");
+
+    plan_file_renames(source, extensions, |file_name, stem, extension| {
+        if !re.is_match(file_name) {
+            return Ok(None);
+        }
+        let replacement = expand_template(template, stem, extension)?;
+        Ok(Some(
+            re.replace_all(file_name, replacement.as_str()).into_owned(),
+        ))
+    })
+}
+
+fn plan_no_intro_rename(source: &Path, extensions: &[String]) -> Result<Plan, Error> {
+    let config = load_name_config(source)?;
+    debug!("Renaming files in {source:?} to No-Intro names");
+
+    plan_file_renames(source, extensions, |_, stem, extension| {
+        let name = convert_name(stem, &config);
+        Ok(Some(if extension.is_empty() {
+            name
+        } else {
+            format!("{}.{}", name, extension)
+        }))
+    })
+}
+
+// Build a single plan for every game below source. Each leaf directory that contains a cue sheet
+// is treated as one game.
+fn plan_recursive_bin_cue_rename(source: &Path) -> Result<Plan, Error> {
+    let mut directories: Vec<PathBuf> = find_files_with_extension(source, &["cue"])?
+        .iter()
+        .filter_map(|cue| cue.parent().map(Path::to_path_buf))
+        .collect();
+    directories.sort();
+    directories.dedup();
+    debug!("Found {} games in {source:?}", directories.len());
+
+    let mut plan = Plan::default();
+    for directory in &directories {
+        if directories
+            .iter()
+            .any(|other| other != directory && other.starts_with(directory))
+        {
+            warn!("{} contains other games. Skipping.", directory.display());
+            continue;
+        }
+
+        match plan_bin_cue_rename(directory, None) {
+            Ok(game_plan) => plan.extend(game_plan),
+            Err(e) => error!("Failed to plan rename for {}: {}", directory.display(), e),
+        }
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use tempdir::TempDir;
+    use test_context::{test_context, TestContext};
+
+    use super::*;
+
+    struct Context {
+        root: TempDir,
+    }
+
+    impl TestContext for Context {
+        fn setup() -> Self {
+            Self {
+                root: TempDir::new("tmp").unwrap(),
             }
         }
+
+        fn teardown(self) {
+            self.root.close().unwrap();
+        }
     }
 
-    Ok(())
+    const CUE: &str = r#"REM Old Name (Track 1).bin
+FILE "Old Name (Track 1).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Old Name (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 01 00:00:00
+"#;
+
+    fn create_game(root: &Path) -> PathBuf {
+        let game = root.join("New Name");
+        fs::create_dir(&game).unwrap();
+        write(game.join("Old Name.cue"), CUE).unwrap();
+        write(game.join("Old Name (Track 1).bin"), "1").unwrap();
+        write(game.join("Old Name (Track 2).bin"), "2").unwrap();
+        game.canonicalize().unwrap()
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_bin_cue_rename_uses_directory_name(ctx: &mut Context) {
+        let game = create_game(ctx.root.path());
+
+        let plan = plan_bin_cue_rename(&game, None).unwrap();
+        assert_eq!(
+            plan.renames,
+            vec![
+                Rename {
+                    from: game.join("Old Name (Track 1).bin"),
+                    to: game.join("New Name (Track 1).bin"),
+                },
+                Rename {
+                    from: game.join("Old Name (Track 2).bin"),
+                    to: game.join("New Name (Track 2).bin"),
+                },
+                Rename {
+                    from: game.join("Old Name.cue"),
+                    to: game.join("New Name.cue"),
+                },
+            ]
+        );
+        assert_eq!(plan.rewrites.len(), 1);
+        assert_eq!(plan.rewrites[0].path, game.join("New Name.cue"));
+        assert_eq!(
+            plan.rewrites[0].after,
+            CUE.replace("FILE \"Old Name", "FILE \"New Name")
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_recursive_bin_cue_rename_plans_every_game(ctx: &mut Context) {
+        let root = ctx.root.path();
+        let game = create_game(root);
+        let other = root.join("Other Game");
+        fs::create_dir(&other).unwrap();
+        write(other.join("Other.cue"), "FILE \"Other.bin\" BINARY\n").unwrap();
+        write(other.join("Other.bin"), "").unwrap();
+        let other = other.canonicalize().unwrap();
+
+        let plan = plan_recursive_bin_cue_rename(&root.canonicalize().unwrap()).unwrap();
+        let targets: Vec<&PathBuf> = plan.renames.iter().map(|rename| &rename.to).collect();
+        assert_eq!(
+            targets,
+            vec![
+                &game.join("New Name (Track 1).bin"),
+                &game.join("New Name (Track 2).bin"),
+                &game.join("New Name.cue"),
+                &other.join("Other Game.bin"),
+                &other.join("Other Game.cue"),
+            ]
+        );
+        assert_eq!(plan.rewrites.len(), 2);
+    }
+
+    #[test]
+    fn expand_template_with_fields() {
+        assert_eq!(
+            expand_template("{title} [{region}] {disc}.{ext:lower}", "Game (USA)", "ISO").unwrap(),
+            "Game [USA] .iso"
+        );
+    }
+
+    #[test]
+    fn expand_template_escapes_dollar_signs() {
+        assert_eq!(
+            expand_template("${1} {title}", "Game $ (USA)", "iso").unwrap(),
+            "${1} Game $$"
+        );
+    }
+
+    #[test]
+    fn expand_template_with_unknown_field_or_filter() {
+        assert!(expand_template("{unknown}", "Game", "iso").is_err());
+        assert!(expand_template("{title:reverse}", "Game", "iso").is_err());
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_pattern_rename_reorders_articles(ctx: &mut Context) {
+        let root = ctx.root.path().canonicalize().unwrap();
+        write(root.join("Game, The (USA) [!].ISO"), "").unwrap();
+        write(root.join("Other (Europe).ISO"), "").unwrap();
+
+        let plan = plan_pattern_rename(
+            &root,
+            r"^(?<title>.+), The(?<rest>.*) \[!\]\.\w+$",
+            "The ${title}${rest}.{ext:lower}",
+            &["ISO".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            plan.renames,
+            vec![Rename {
+                from: root.join("Game, The (USA) [!].ISO"),
+                to: root.join("The Game (USA).iso"),
+            }]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_pattern_rename_rewrites_cue_sheets_next_to_renamed_files(ctx: &mut Context) {
+        let game = create_game(ctx.root.path());
+
+        let plan = plan_pattern_rename(&game, r"^Old", "Older", &["bin".to_string()]).unwrap();
+        assert_eq!(plan.renames.len(), 2);
+        assert_eq!(
+            plan.rewrites,
+            vec![Rewrite {
+                path: game.join("Old Name.cue"),
+                before: CUE.to_string(),
+                after: CUE.replace("FILE \"Old Name", "FILE \"Older Name"),
+            }]
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn plan_no_intro_rename_renames_bins_and_cue(ctx: &mut Context) {
+        let root = ctx.root.path().canonicalize().unwrap();
+        let game = root.join("Game (U)");
+        fs::create_dir(&game).unwrap();
+        write(
+            game.join("Game (U) [!].cue"),
+            CUE.replace("FILE \"Old Name", "FILE \"Game (U) [!]"),
+        )
+        .unwrap();
+        write(game.join("Game (U) [!] (Track 1).bin"), "").unwrap();
+        write(game.join("Game (U) [!] (Track 2).bin"), "").unwrap();
+
+        let plan = plan_no_intro_rename(&root, &[]).unwrap();
+        assert_eq!(
+            plan.renames,
+            vec![
+                Rename {
+                    from: game.join("Game (U) [!] (Track 1).bin"),
+                    to: game.join("Game (USA) (Track 1).bin"),
+                },
+                Rename {
+                    from: game.join("Game (U) [!] (Track 2).bin"),
+                    to: game.join("Game (USA) (Track 2).bin"),
+                },
+                Rename {
+                    from: game.join("Game (U) [!].cue"),
+                    to: game.join("Game (USA).cue"),
+                },
+            ]
+        );
+        assert_eq!(
+            plan.rewrites[0].after,
+            CUE.replace("FILE \"Old Name", "FILE \"Game (USA)")
+        );
+    }
+
+    #[test_context(Context)]
+    #[test]
+    fn run_plan_and_undo(ctx: &mut Context) {
+        let game = create_game(ctx.root.path());
+        let journal = ctx.root.path().join("journal.toml");
+
+        let plan = plan_bin_cue_rename(&game, None).unwrap();
+        run_plan(&plan, &journal, false).unwrap();
+        assert!(game.join("New Name (Track 1).bin").exists());
+        assert_eq!(
+            read_to_string(game.join("New Name.cue")).unwrap(),
+            CUE.replace("FILE \"Old Name", "FILE \"New Name")
+        );
+
+        undo(&journal, false).unwrap();
+        assert!(game.join("Old Name (Track 1).bin").exists());
+        assert!(!game.join("New Name (Track 1).bin").exists());
+        assert_eq!(read_to_string(game.join("Old Name.cue")).unwrap(), CUE);
+        assert!(!journal.exists());
+    }
 }
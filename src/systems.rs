@@ -0,0 +1,372 @@
+use super::config::System;
+
+use Media::{Cartridge, Disc, Floppy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Media {
+    Cartridge,
+    Disc,
+    Floppy,
+}
+
+// A system that retro knows about. The name is the folder frontends (e.g., EmulationStation and
+// RetroArch's playlists) look for the system's games in, and the full name is the one No-Intro,
+// Redump, or TOSEC use for it. Extensions are in order of preference. Discs only list images and
+// descriptors, since the tracks a descriptor references are linked along with it.
+#[derive(Debug, PartialEq)]
+pub struct KnownSystem {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub full_name: &'static str,
+    pub dumper: &'static str,
+    pub media: Media,
+    pub extensions: &'static [&'static str],
+}
+
+impl KnownSystem {
+    pub fn get_extensions(&self) -> Vec<String> {
+        self.extensions.iter().map(|ext| ext.to_string()).collect()
+    }
+
+    // The settings a system in a config file gets when it doesn't set them itself. The destination
+    // is left to the config's key (or a preset) so that an alias links to the folder it's named
+    // after.
+    pub fn to_system(&self) -> System {
+        System {
+            dumper: vec![self.dumper.to_string()],
+            extensions: Some(self.get_extensions()),
+            ..Default::default()
+        }
+    }
+}
+
+const fn system(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    full_name: &'static str,
+    dumper: &'static str,
+    media: Media,
+    extensions: &'static [&'static str],
+) -> KnownSystem {
+    KnownSystem {
+        name,
+        aliases,
+        full_name,
+        dumper,
+        media,
+        extensions,
+    }
+}
+
+pub const SYSTEMS: [KnownSystem; 34] = [
+    system(
+        "3do",
+        &["panasonic3do"],
+        "Panasonic - 3DO Interactive Multiplayer",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "amiga",
+        &["commodoreamiga"],
+        "Commodore - Amiga",
+        "tosec",
+        Floppy,
+        &["adf", "m3u"],
+    ),
+    system(
+        "amstradcpc",
+        &["cpc"],
+        "Amstrad - CPC",
+        "tosec",
+        Floppy,
+        &["dsk", "m3u"],
+    ),
+    system(
+        "atari2600",
+        &["2600", "a2600"],
+        "Atari - 2600",
+        "no-intro",
+        Cartridge,
+        &["a26", "bin"],
+    ),
+    system(
+        "atari7800",
+        &["7800", "a7800"],
+        "Atari - 7800",
+        "no-intro",
+        Cartridge,
+        &["a78", "bin"],
+    ),
+    system(
+        "atarilynx",
+        &["lynx"],
+        "Atari - Lynx",
+        "no-intro",
+        Cartridge,
+        &["lnx"],
+    ),
+    system(
+        "c64",
+        &["commodore64"],
+        "Commodore - Commodore 64",
+        "tosec",
+        Floppy,
+        &["d64", "t64", "prg", "m3u"],
+    ),
+    system(
+        "dreamcast",
+        &["dc"],
+        "Sega - Dreamcast",
+        "redump",
+        Disc,
+        &["chd", "gdi", "cue", "m3u"],
+    ),
+    system(
+        "gamegear",
+        &["gg"],
+        "Sega - Game Gear",
+        "no-intro",
+        Cartridge,
+        &["gg"],
+    ),
+    system(
+        "gb",
+        &["gameboy"],
+        "Nintendo - Game Boy",
+        "no-intro",
+        Cartridge,
+        &["gb"],
+    ),
+    system(
+        "gba",
+        &["gameboyadvance"],
+        "Nintendo - Game Boy Advance",
+        "no-intro",
+        Cartridge,
+        &["gba"],
+    ),
+    system(
+        "gbc",
+        &["gameboycolor"],
+        "Nintendo - Game Boy Color",
+        "no-intro",
+        Cartridge,
+        &["gbc"],
+    ),
+    system(
+        "gc",
+        &["gamecube", "ngc"],
+        "Nintendo - GameCube",
+        "redump",
+        Disc,
+        &["rvz", "iso", "m3u"],
+    ),
+    system(
+        "mastersystem",
+        &["sms"],
+        "Sega - Master System - Mark III",
+        "no-intro",
+        Cartridge,
+        &["sms"],
+    ),
+    system(
+        "megadrive",
+        &["genesis", "md"],
+        "Sega - Mega Drive - Genesis",
+        "no-intro",
+        Cartridge,
+        &["md", "gen", "bin"],
+    ),
+    system(
+        "n64",
+        &["nintendo64"],
+        "Nintendo - Nintendo 64",
+        "no-intro",
+        Cartridge,
+        &["z64", "n64", "v64"],
+    ),
+    system(
+        "nds",
+        &["ds"],
+        "Nintendo - Nintendo DS",
+        "no-intro",
+        Cartridge,
+        &["nds"],
+    ),
+    system(
+        "neogeocd",
+        &["ngcd"],
+        "SNK - Neo Geo CD",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "nes",
+        &["famicom"],
+        "Nintendo - Nintendo Entertainment System",
+        "no-intro",
+        Cartridge,
+        &["nes"],
+    ),
+    system(
+        "ngp",
+        &["neogeopocket"],
+        "SNK - Neo Geo Pocket",
+        "no-intro",
+        Cartridge,
+        &["ngp"],
+    ),
+    system(
+        "ngpc",
+        &["neogeopocketcolor"],
+        "SNK - Neo Geo Pocket Color",
+        "no-intro",
+        Cartridge,
+        &["ngc"],
+    ),
+    system(
+        "pcengine",
+        &["pce", "tg16", "turbografx16"],
+        "NEC - PC Engine - TurboGrafx-16",
+        "no-intro",
+        Cartridge,
+        &["pce"],
+    ),
+    system(
+        "pcenginecd",
+        &["pcecd", "tgcd", "turbografxcd"],
+        "NEC - PC Engine CD & TurboGrafx CD",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "pcfx",
+        &[],
+        "NEC - PC-FX",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "ps2",
+        &["playstation2"],
+        "Sony - PlayStation 2",
+        "redump",
+        Disc,
+        &["chd", "iso", "m3u"],
+    ),
+    system(
+        "psp",
+        &["playstationportable"],
+        "Sony - PlayStation Portable",
+        "redump",
+        Disc,
+        &["cso", "iso"],
+    ),
+    system(
+        "psx",
+        &["ps1", "playstation"],
+        "Sony - PlayStation",
+        "redump",
+        Disc,
+        &["chd", "cue", "pbp", "m3u"],
+    ),
+    system(
+        "saturn",
+        &["ss"],
+        "Sega - Saturn",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "sega32x",
+        &["32x"],
+        "Sega - 32X",
+        "no-intro",
+        Cartridge,
+        &["32x"],
+    ),
+    system(
+        "segacd",
+        &["megacd", "scd"],
+        "Sega - Mega-CD - Sega CD",
+        "redump",
+        Disc,
+        &["chd", "cue", "m3u"],
+    ),
+    system(
+        "snes",
+        &["superfamicom", "sfc"],
+        "Nintendo - Super Nintendo Entertainment System",
+        "no-intro",
+        Cartridge,
+        &["sfc", "smc"],
+    ),
+    system(
+        "virtualboy",
+        &["vb"],
+        "Nintendo - Virtual Boy",
+        "no-intro",
+        Cartridge,
+        &["vb"],
+    ),
+    system(
+        "wii",
+        &[],
+        "Nintendo - Wii",
+        "redump",
+        Disc,
+        &["rvz", "iso", "m3u"],
+    ),
+    system(
+        "zxspectrum",
+        &["spectrum"],
+        "Sinclair - ZX Spectrum +3",
+        "tosec",
+        Floppy,
+        &["dsk", "tzx", "tap", "m3u"],
+    ),
+];
+
+// Find a known system by its name, one of its aliases, or its full name.
+pub fn find_system(name: &str) -> Option<&'static KnownSystem> {
+    SYSTEMS.iter().find(|system| {
+        system.name.eq_ignore_ascii_case(name)
+            || system.full_name.eq_ignore_ascii_case(name)
+            || system
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn find_system_by_name_alias_or_full_name() {
+        assert_eq!(find_system("psx").unwrap().name, "psx");
+        assert_eq!(find_system("PS1").unwrap().name, "psx");
+        assert_eq!(find_system("Sony - PlayStation").unwrap().name, "psx");
+        assert_eq!(find_system("genesis").unwrap().name, "megadrive");
+        assert!(find_system("unknown").is_none());
+    }
+
+    #[test]
+    fn systems_names_are_unique() {
+        let mut names = HashSet::new();
+        for system in &SYSTEMS {
+            for name in std::iter::once(&system.name).chain(system.aliases) {
+                assert!(names.insert(*name), "{name} is used more than once");
+            }
+        }
+    }
+}
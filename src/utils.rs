@@ -1,16 +1,42 @@
 use std::env::{current_dir, var, VarError};
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::process::Command;
+use std::sync::OnceLock;
 
-use log::error;
+use regex::Regex;
+
+use super::error::Error;
+
+static VARIABLE_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+fn get_program(command: &Command) -> String {
+    command.get_program().to_string_lossy().to_string()
+}
+
+fn spawn_error(command: &Command, expected_message: &str, error: io::Error) -> Error {
+    if error.kind() == io::ErrorKind::NotFound {
+        Error::MissingTool(get_program(command))
+    } else {
+        Error::Other(format!("{}: {}", expected_message, error))
+    }
+}
 
 pub fn capture_output<'a>(
     command: &'a mut Command,
     expected_message: &'a str,
-) -> Result<String, String> {
+) -> Result<String, Error> {
     let output = command
         .output()
-        .map_err(|e| format!("{}: {}", expected_message, e))?;
+        .map_err(|e| spawn_error(command, expected_message, e))?;
+
+    if !output.status.success() {
+        return Err(Error::Command {
+            command: get_program(command),
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
 
     let mut result = String::from_utf8(output.stdout)
         .map_err(|e| format!("Failed to decode UTF-8 in command output: {}", e))?;
@@ -20,20 +46,14 @@ pub fn capture_output<'a>(
     Ok(result)
 }
 
-pub fn find_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+pub fn find_files(root: &Path) -> Result<Vec<PathBuf>, Error> {
     let mut files_found = Vec::new();
     let entries = root
         .read_dir()
-        .map_err(|e| format!("Failed to read directory {}: {}", root.display(), e))?;
+        .map_err(|e| Error::io("Failed to read directory", root, e))?;
 
     for entry in entries {
-        let entry = entry.map_err(|e| {
-            format!(
-                "Failed to read directory entry in {}: {}",
-                root.display(),
-                e
-            )
-        })?;
+        let entry = entry.map_err(|e| Error::io("Failed to read directory entry in", root, e))?;
         let path = entry.path();
         if path.is_dir() {
             files_found.append(&mut find_files(&path)?);
@@ -45,12 +65,12 @@ pub fn find_files(root: &Path) -> Result<Vec<PathBuf>, String> {
     Ok(files_found)
 }
 
-pub fn find_files_with_extension(root: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, String> {
+pub fn find_files_with_extension(root: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, Error> {
     let mut files_found = Vec::new();
     for file in find_files(root)? {
         if let Some(extension) = file.extension() {
             if let Some(extension) = extension.to_str() {
-                if extensions.iter().any(|ext| *ext == extension) {
+                if extensions.contains(&extension) {
                     files_found.push(file);
                 }
             }
@@ -60,9 +80,9 @@ pub fn find_files_with_extension(root: &Path, extensions: &[&str]) -> Result<Vec
     Ok(files_found)
 }
 
-pub fn find_file_recursively(root: &Path, name: &str) -> Result<Option<PathBuf>, String> {
+pub fn find_file_recursively(root: &Path, name: &str) -> Result<Option<PathBuf>, Error> {
     let mut path: PathBuf = root.into();
-    if path == PathBuf::from(".") {
+    if path == Path::new(".") {
         path = current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
     }
     let file = Path::new(name);
@@ -84,14 +104,57 @@ pub fn get_from_env(name: &str) -> Result<String, VarError> {
     var(name)
 }
 
-pub fn get_from_env_or_exit(name: &str) -> String {
-    match var(name) {
-        Ok(value) => value,
-        Err(error) => {
-            error!("{name:?}: {error}");
-            exit(1);
+// Expand a leading `~` and any `$VAR` or `${VAR}` in a value (`$$` is a literal `$`). The names of
+// variables that aren't set are added to unresolved, and they're left in the value as they were.
+// A variable without a name (`${}`) is an error.
+pub fn expand_variables(value: &str, unresolved: &mut Vec<String>) -> Result<String, Error> {
+    let re = VARIABLE_PATTERN.get_or_init(|| {
+        Regex::new(r"\$(?:\{(?<braced>[^}]*)\}|(?<name>[A-Za-z_][A-Za-z0-9_]*)|(?<dollar>\$))")
+            .expect("Failed to compile regex pattern")
+    });
+
+    let mut expanded = String::new();
+    let mut rest = value;
+    if rest == "~" || rest.starts_with("~/") {
+        match var("HOME") {
+            Ok(home) => {
+                expanded.push_str(&home);
+                rest = &rest[1..];
+            }
+            Err(_) => unresolved.push("HOME".to_string()),
+        }
+    }
+
+    let mut last = 0;
+    for capture in re.captures_iter(rest) {
+        let Some(full_match) = capture.get(0) else {
+            continue;
+        };
+        expanded.push_str(&rest[last..full_match.start()]);
+        last = full_match.end();
+
+        if capture.name("dollar").is_some() {
+            expanded.push('$');
+            continue;
+        }
+        let name = capture
+            .name("braced")
+            .or(capture.name("name"))
+            .map_or("", |m| m.as_str());
+        if name.is_empty() {
+            return Err(Error::Config(format!("Empty variable name in '{}'", value)));
+        }
+        match var(name) {
+            Ok(value) => expanded.push_str(&value),
+            _ => {
+                unresolved.push(name.to_string());
+                expanded.push_str(full_match.as_str());
+            }
         }
     }
+    expanded.push_str(&rest[last..]);
+
+    Ok(expanded)
 }
 
 // Adapted from https://users.rust-lang.org/t/is-this-code-idiomatic/51798/2.
@@ -102,9 +165,9 @@ pub fn longest_common_prefix(vals: &[String]) -> &str {
 
     let common = &vals[0];
 
-    for (i, c) in common.chars().enumerate() {
+    for (i, c) in common.char_indices() {
         for val in vals {
-            if val.chars().nth(i) != Some(c) {
+            if val.get(i..).and_then(|rest| rest.chars().next()) != Some(c) {
                 return &common[..i];
             }
         }
@@ -113,28 +176,47 @@ pub fn longest_common_prefix(vals: &[String]) -> &str {
     common
 }
 
-pub fn require_command(command: &str) -> Result<Command, String> {
+pub fn require_command(command: &str) -> Result<Command, Error> {
     if let Ok(output) = Command::new("which").arg(command).output() {
         if output.status.success() {
             return Ok(Command::new(command));
         }
     }
 
-    Err(format!("Failed to find command '{}' in PATH", command))
+    Err(Error::MissingTool(command.to_string()))
 }
 
-pub fn stream_output(command: &mut Command, expected_message: &str) -> Result<(), String> {
+// Split off the first token of a line, honoring double quotes around names with spaces.
+pub fn split_token(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if let Some(rest) = line.strip_prefix('"') {
+        let end = rest.find('"')?;
+        Some((&rest[..end], &rest[end + 1..]))
+    } else {
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        if end == 0 {
+            return None;
+        }
+        Some((&line[..end], &line[end..]))
+    }
+}
+
+pub fn stream_output(command: &mut Command, expected_message: &str) -> Result<(), Error> {
     let mut child = command
         .spawn()
-        .map_err(|e| format!("{}: {}", expected_message, e))?;
+        .map_err(|e| spawn_error(command, expected_message, e))?;
 
     let exit_status = child
         .wait()
         .map_err(|e| format!("Failed to wait for command: {}", e))?;
 
+    // The command's output was already streamed, so there's no stderr to include.
     if !exit_status.success() {
-        let code = exit_status.code().unwrap_or(1);
-        return Err(format!("Failed to execute command: exit code {}", code));
+        return Err(Error::Command {
+            command: get_program(command),
+            status: exit_status.code(),
+            stderr: String::new(),
+        });
     }
 
     Ok(())
@@ -142,6 +224,7 @@ pub fn stream_output(command: &mut Command, expected_message: &str) -> Result<()
 
 #[cfg(test)]
 mod test {
+    use std::env::set_var;
     use std::fs::{create_dir, File};
 
     use tempdir::TempDir;
@@ -169,7 +252,7 @@ mod test {
     #[test_context(Context)]
     #[test]
     fn find_file_recursively_does_not_find_file_in_child(ctx: &mut Context) {
-        let _ = create_dir(ctx.root.path().join("child")).unwrap();
+        create_dir(ctx.root.path().join("child")).unwrap();
         let file_path = ctx.root.path().join("child").join("test");
         let _ = File::create(&file_path).unwrap();
 
@@ -260,4 +343,65 @@ mod test {
             "abc"
         );
     }
+
+    #[test]
+    fn expand_variables_expands_embedded_variables() {
+        set_var("TEST_EXPAND_VARIABLES_ROOT", "/games");
+        set_var("TEST_EXPAND_VARIABLES_SYSTEM", "psx");
+        let mut unresolved = Vec::new();
+        assert_eq!(
+            expand_variables(
+                "$TEST_EXPAND_VARIABLES_ROOT/${TEST_EXPAND_VARIABLES_SYSTEM}-$$1",
+                &mut unresolved
+            )
+            .unwrap(),
+            "/games/psx-$1"
+        );
+        assert!(unresolved.is_empty());
+    }
+
+    #[test]
+    fn expand_variables_expands_home() {
+        let home = var("HOME").unwrap();
+        let mut unresolved = Vec::new();
+        assert_eq!(
+            expand_variables("~/games", &mut unresolved).unwrap(),
+            format!("{home}/games")
+        );
+        assert_eq!(expand_variables("a/~", &mut unresolved).unwrap(), "a/~");
+        assert!(unresolved.is_empty());
+    }
+
+    #[test]
+    fn expand_variables_reports_unresolved_variables() {
+        let mut unresolved = Vec::new();
+        let expanded = expand_variables(
+            "${TEST_EXPAND_VARIABLES_MISSING_1}/$TEST_EXPAND_VARIABLES_MISSING_2",
+            &mut unresolved,
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            "${TEST_EXPAND_VARIABLES_MISSING_1}/$TEST_EXPAND_VARIABLES_MISSING_2"
+        );
+        assert_eq!(
+            unresolved,
+            vec![
+                "TEST_EXPAND_VARIABLES_MISSING_1",
+                "TEST_EXPAND_VARIABLES_MISSING_2"
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_variables_rejects_empty_names() {
+        let mut unresolved = Vec::new();
+        assert_eq!(
+            expand_variables("/games/${}", &mut unresolved)
+                .unwrap_err()
+                .to_string(),
+            "Empty variable name in '/games/${}'"
+        );
+        assert!(unresolved.is_empty());
+    }
 }